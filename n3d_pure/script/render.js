@@ -1,53 +1,59 @@
 import init, { Renderer } from '../pkg/renderer.js';
-let isDragging = false, isInCanvas = false;
+let isDragging = false, isPanning = false, isInCanvas = false;
+let lastX = 0, lastY = 0;
 // Load the WASM module
 await init();
-const renderer = await new Renderer();
-await load_3();
+const canvas_ = document.getElementById("canvas");
+let renderer;
+try {
+    // autoResize sizes the surface to the canvas' layout size * devicePixelRatio and follows it;
+    // more viewers on the page can reuse the GPU device with renderer.share_device(otherCanvas, {...})
+    renderer = await new Renderer(canvas_, { autoResize: true });
+    await load_3();
+    renderer.start();
+} catch (err) {
+    showError(err);
+}
 // set default camera values here
 
-// resizing window
-let resizeTimer;
-window.addEventListener("resize", async () => {
-    clearTimeout(resizeTimer);
-    resizeTimer = setTimeout(() => {
-        console.log("Resize finished!");
-        console.log("New size:", window.innerWidth, "x", window.innerHeight);
-    }, 300); // 300ms after last resize event
-    //await renderer.reconfigure_surface();
-});
 // -------------------------------- canvas ------------------------------------------
-const canvas_ = document.getElementById("canvas");
-const rect = canvas.getBoundingClientRect();
-let width = canvas.width;
-let height = canvas.height;
+// mouse math is done in CSS pixels; the canvas backing store may be larger (devicePixelRatio)
+let rect = canvas.getBoundingClientRect();
 // Mouse down (start drag)
 canvas_.addEventListener("mousedown", (e) => {
-    if (e.button === 0) { // left button
+    if (e.button === 0 && !e.shiftKey) { // left button orbits
         isDragging = true;
-       
+    } else if (e.button === 2 || (e.button === 0 && e.shiftKey)) { // right or shift+left pans
+        isPanning = true;
     }
-
+    rect = canvas.getBoundingClientRect();
+    lastX = e.clientX - rect.left;
+    lastY = e.clientY - rect.top;
 });
+canvas_.addEventListener("contextmenu", (e) => e.preventDefault());
 // Mouse move (if dragging, update rotation)
 canvas.addEventListener("mousemove", (e) => {
     let x = e.clientX - rect.left;
     let y = e.clientY - rect.top;
     if (isDragging) {
-        // camera update happens here
-        console.log("rotating model");
-        let ndcX = (x / width) * 2.0 - 1.0;
-        let ndcY = 1.0 - (y / height) * 2.0;
-        console.log("ndcX: ", ndcX, "ndcY: ", ndcY)
+        // a drag across the full canvas width turns the camera half a circle
+        renderer.rotate(Math.PI * (x - lastX) / rect.width, Math.PI * (y - lastY) / rect.height);
+    } else if (isPanning) {
+        // pixel delta -> NDC delta (y points up in NDC)
+        renderer.pan(2.0 * (x - lastX) / rect.width, -2.0 * (y - lastY) / rect.height);
     }
+    lastX = x;
+    lastY = y;
 });
 // Mouse up (stop drag)
 canvas.addEventListener("mouseup", () => {
     isDragging = false;
+    isPanning = false;
 });
 // Also stop if mouse leaves canvas while dragging
 canvas.addEventListener("mouseleave", () => {
     isDragging = false;
+    isPanning = false;
     isInCanvas = false;
 });
 canvas.addEventListener("mouseenter", () => {
@@ -60,11 +66,8 @@ canvas.addEventListener("wheel", (e) => {
     if (isInCanvas) {
         e.preventDefault(); // <-- stop the page from scrolling
 
-        if (e.deltaY < 0) { // scroll up
-            console.log("zoomed in");
-        } else if (e.deltaY > 0) { // scroll down
-            console.log("zoomed out");
-        }
+        // scroll up zooms in, scroll down zooms out
+        renderer.zoom(e.deltaY * 0.001);
     }
 }, { passive: false }); // IMPORTANT: allows preventDefault
 
@@ -77,5 +80,31 @@ async function load_3() {
     //const response = await fetch("models/t_rex_model_glb/source/trex.glb");
     const response = await fetch("models/flightHelmet/flightHelmet.glb");
     const bytes = new Uint8Array(await response.arrayBuffer());
-    const result = await renderer.render(bytes);
+    const result = await renderer.load_model(bytes);
+}
+
+// replace the canvas with a readable message (err.kind comes from RendererError)
+function showError(err) {
+    console.error(err);
+    const message = document.createElement("p");
+    if (err.kind === "NoAdapter" || err.kind === "SurfaceCreation") {
+        message.textContent = "3D preview needs a browser with WebGPU support.";
+    } else if (err.kind === "GltfParse" || err.kind === "UnsupportedImageFormat") {
+        message.textContent = "This model could not be displayed (" + err.message + ").";
+    } else {
+        message.textContent = "3D preview unavailable: " + (err.message ?? err);
+    }
+    canvas_.replaceWith(message);
 }
+
+// pause the frame loop while the tab is hidden
+document.addEventListener("visibilitychange", () => {
+    if (!renderer) {
+        return;
+    }
+    if (document.hidden) {
+        renderer.stop();
+    } else {
+        renderer.start();
+    }
+});
@@ -0,0 +1,497 @@
+use crate::scene_graph::SceneGraph;
+use glam::{Quat, Vec3};
+use gltf::animation::util::ReadOutputs;
+use gltf::animation::{Interpolation, Property};
+
+// One glTF animation: channels that key node transforms and morph target weights
+#[derive(Debug, Clone)]
+pub struct Clip {
+    pub name: Option<String>,
+    // last keyframe time of any channel, in seconds
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    // `width` floats per element; CUBICSPLINE keys hold in-tangent, value, out-tangent
+    pub values: Vec<f32>,
+    pub width: usize,
+}
+
+// Local transform and morph weights of one node
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodePose {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    pub weights: Vec<f32>,
+}
+
+impl NodePose {
+    fn blend(&self, other: &NodePose, t: f32) -> NodePose {
+        let weights = (0..self.weights.len().max(other.weights.len()))
+            .map(|i| {
+                let a = self.weights.get(i).copied().unwrap_or(0.0);
+                let b = other.weights.get(i).copied().unwrap_or(0.0);
+                a + (b - a) * t
+            })
+            .collect();
+        NodePose {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+            weights,
+        }
+    }
+}
+
+impl Clip {
+    pub fn from_gltf(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> Clip {
+        let channels: Vec<Channel> = animation
+            .channels()
+            .filter_map(|channel| Channel::from_gltf(&channel, buffers))
+            .collect();
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Clip {
+            name: animation.name().map(str::to_string),
+            duration,
+            channels,
+        }
+    }
+
+    // Overwrites the animated properties of `pose` with their values at `time`
+    fn sample(&self, time: f32, pose: &mut [NodePose]) {
+        for channel in &self.channels {
+            let Some(node) = pose.get_mut(channel.node) else {
+                continue;
+            };
+            let value = channel.sample(time);
+            match channel.property {
+                Property::Translation => node.translation = Vec3::from_slice(&value),
+                Property::Rotation => node.rotation = Quat::from_slice(&value).normalize(),
+                Property::Scale => node.scale = Vec3::from_slice(&value),
+                Property::MorphTargetWeights => node.weights = value,
+            }
+        }
+    }
+}
+
+impl Channel {
+    fn from_gltf(
+        channel: &gltf::animation::Channel,
+        buffers: &[gltf::buffer::Data],
+    ) -> Option<Channel> {
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let interpolation = channel.sampler().interpolation();
+        let times: Vec<f32> = reader.read_inputs()?.collect();
+        let elements = times.len()
+            * match interpolation {
+                Interpolation::CubicSpline => 3,
+                _ => 1,
+            };
+        let (values, width): (Vec<f32>, usize) = match reader.read_outputs()? {
+            ReadOutputs::Translations(values) => (values.flatten().collect(), 3),
+            ReadOutputs::Rotations(values) => (values.into_f32().flatten().collect(), 4),
+            ReadOutputs::Scales(values) => (values.flatten().collect(), 3),
+            // one weight per morph target in every element
+            ReadOutputs::MorphTargetWeights(values) => {
+                let values: Vec<f32> = values.into_f32().collect();
+                let width = values.len().checked_div(elements).unwrap_or(0);
+                (values, width)
+            }
+        };
+        if times.is_empty() || width == 0 || values.len() < elements * width {
+            return None;
+        }
+        Some(Channel {
+            node: channel.target().node().index(),
+            property: channel.target().property(),
+            interpolation,
+            times,
+            values,
+            width,
+        })
+    }
+
+    // Value list element of `key`; `part` picks in-tangent (0), value (1) or out-tangent (2)
+    // of CUBICSPLINE keys and is ignored otherwise
+    fn key(&self, key: usize, part: usize) -> &[f32] {
+        let element = match self.interpolation {
+            Interpolation::CubicSpline => key * 3 + part,
+            _ => key,
+        };
+        &self.values[element * self.width..(element + 1) * self.width]
+    }
+
+    // Value at `time`, held at the first and last keys outside the keyed range
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        const VALUE: usize = 1;
+        let value_part = match self.interpolation {
+            Interpolation::CubicSpline => VALUE,
+            _ => 0,
+        };
+        let last = self.times.len() - 1;
+        // first key after `time`
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return self.key(0, value_part).to_vec();
+        }
+        if next > last {
+            return self.key(last, value_part).to_vec();
+        }
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = if delta > 0.0 {
+            (time - self.times[previous]) / delta
+        } else {
+            0.0
+        };
+        match self.interpolation {
+            Interpolation::Step => self.key(previous, 0).to_vec(),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let a = Quat::from_slice(self.key(previous, 0));
+                let b = Quat::from_slice(self.key(next, 0));
+                a.slerp(b, t).to_array().to_vec()
+            }
+            Interpolation::Linear => {
+                let (a, b) = (self.key(previous, 0), self.key(next, 0));
+                a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect()
+            }
+            Interpolation::CubicSpline => {
+                // Hermite spline between the two values, tangents scaled by the key spacing
+                const IN_TANGENT: usize = 0;
+                const OUT_TANGENT: usize = 2;
+                let (t2, t3) = (t * t, t * t * t);
+                let p0 = self.key(previous, VALUE);
+                let m0 = self.key(previous, OUT_TANGENT);
+                let p1 = self.key(next, VALUE);
+                let m1 = self.key(next, IN_TANGENT);
+                (0..self.width)
+                    .map(|i| {
+                        (2.0 * t3 - 3.0 * t2 + 1.0) * p0[i]
+                            + (t3 - 2.0 * t2 + t) * delta * m0[i]
+                            + (-2.0 * t3 + 3.0 * t2) * p1[i]
+                            + (t3 - t2) * delta * m1[i]
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClipTime {
+    clip: usize,
+    time: f32,
+}
+
+// Clip being faded out after play() was asked to cross-fade
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    from: ClipTime,
+    elapsed: f32,
+    duration: f32,
+}
+
+// Playback state of a model's clips. Nodes a clip doesn't animate stay in their glTF pose.
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer {
+    rest: Vec<NodePose>,
+    current: Option<ClipTime>,
+    fade: Option<Fade>,
+    pub playing: bool,
+    // wrap around at the ends instead of stopping there
+    pub looping: bool,
+    // playback rate, negative plays backwards
+    pub speed: f32,
+}
+
+impl AnimationPlayer {
+    pub fn new(scene: &SceneGraph) -> AnimationPlayer {
+        AnimationPlayer {
+            rest: scene
+                .nodes
+                .iter()
+                .map(|node| NodePose {
+                    translation: node.translation,
+                    rotation: node.rotation,
+                    scale: node.scale,
+                    weights: node.weights.clone(),
+                })
+                .collect(),
+            current: None,
+            fade: None,
+            playing: false,
+            looping: true,
+            speed: 1.0,
+        }
+    }
+
+    // Playing clip, None before the first play()
+    pub fn current(&self) -> Option<usize> {
+        self.current.map(|current| current.clip)
+    }
+
+    // Seconds into the current clip
+    pub fn time(&self) -> f32 {
+        self.current.map_or(0.0, |current| current.time)
+    }
+
+    // Starts `clip` from its beginning (its end when the speed is negative). With `fade`
+    // seconds the clip that was playing blends out over that time.
+    pub fn play(&mut self, clips: &[Clip], clip: usize, fade: f32) {
+        let start = if self.speed < 0.0 {
+            clips[clip].duration
+        } else {
+            0.0
+        };
+        self.fade = match self.current {
+            Some(from) if fade > 0.0 => Some(Fade {
+                from,
+                elapsed: 0.0,
+                duration: fade,
+            }),
+            _ => None,
+        };
+        self.current = Some(ClipTime { clip, time: start });
+        self.playing = true;
+    }
+
+    // Jumps to `time` seconds into the current clip, clamped to its length
+    pub fn seek(&mut self, clips: &[Clip], time: f32) {
+        if let Some(current) = self.current.as_mut() {
+            current.time = time.clamp(0.0, clips[current.clip].duration);
+        }
+    }
+
+    // Morph weights `node` returns to where no clip animates them
+    pub fn set_rest_weights(&mut self, node: usize, weights: &[f32]) {
+        if let Some(pose) = self.rest.get_mut(node) {
+            pose.weights = weights.to_vec();
+        }
+    }
+
+    // Moves the playhead by `delta` seconds of wall-clock time
+    pub fn advance(&mut self, clips: &[Clip], delta: f32) {
+        if !self.playing {
+            return;
+        }
+        let step = delta * self.speed;
+        let mut ended = false;
+        if let Some(current) = self.current.as_mut() {
+            let duration = clips[current.clip].duration;
+            let time;
+            (time, ended) = step_time(current.time, step, duration, self.looping);
+            current.time = time;
+        }
+        if let Some(fade) = self.fade.as_mut() {
+            let duration = clips[fade.from.clip].duration;
+            fade.from.time = step_time(fade.from.time, step, duration, self.looping).0;
+            fade.elapsed += delta;
+            // a clip that ends mid-fade holds its own last pose, not a blend with the old clip
+            if fade.elapsed >= fade.duration || ended {
+                self.fade = None;
+            }
+        }
+        if ended {
+            self.playing = false;
+        }
+    }
+
+    // Writes the current pose into `scene` and recomputes its world matrices
+    pub fn apply(&self, clips: &[Clip], scene: &mut SceneGraph) {
+        let Some(current) = self.current else {
+            return;
+        };
+        let mut pose = self.rest.clone();
+        clips[current.clip].sample(current.time, &mut pose);
+        if let Some(fade) = self.fade {
+            let mut from = self.rest.clone();
+            clips[fade.from.clip].sample(fade.from.time, &mut from);
+            let t = (fade.elapsed / fade.duration).clamp(0.0, 1.0);
+            pose = from
+                .iter()
+                .zip(&pose)
+                .map(|(from, to)| from.blend(to, t))
+                .collect();
+        }
+        for (node, pose) in scene.nodes.iter_mut().zip(pose) {
+            node.translation = pose.translation;
+            node.rotation = pose.rotation;
+            node.scale = pose.scale;
+            node.weights = pose.weights;
+        }
+        scene.update_world_matrices();
+    }
+}
+
+// Playhead after moving `step` seconds: wrapped into the clip when looping, otherwise held at
+// the end it ran into, with `true` once it got there
+fn step_time(time: f32, step: f32, duration: f32, looping: bool) -> (f32, bool) {
+    if duration <= 0.0 {
+        return (0.0, !looping);
+    }
+    let time = time + step;
+    if looping {
+        return (time.rem_euclid(duration), false);
+    }
+    let ended = (step > 0.0 && time >= duration) || (step < 0.0 && time <= 0.0);
+    (time.clamp(0.0, duration), ended)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(
+        property: Property,
+        interpolation: Interpolation,
+        times: &[f32],
+        values: &[f32],
+    ) -> Channel {
+        let elements = match interpolation {
+            Interpolation::CubicSpline => times.len() * 3,
+            _ => times.len(),
+        };
+        Channel {
+            node: 0,
+            property,
+            interpolation,
+            times: times.to_vec(),
+            values: values.to_vec(),
+            width: values.len() / elements,
+        }
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn step_holds_the_previous_key() {
+        let weights = channel(
+            Property::MorphTargetWeights,
+            Interpolation::Step,
+            &[0.0, 1.0, 2.0],
+            &[0.0, 10.0, 20.0],
+        );
+        assert_close(&weights.sample(0.5), &[0.0]);
+        assert_close(&weights.sample(1.0), &[10.0]);
+        assert_close(&weights.sample(1.99), &[10.0]);
+    }
+
+    #[test]
+    fn linear_lerps_values_and_slerps_rotations() {
+        let translation = channel(
+            Property::Translation,
+            Interpolation::Linear,
+            &[1.0, 3.0],
+            &[0.0, 0.0, 0.0, 4.0, -2.0, 8.0],
+        );
+        assert_close(&translation.sample(1.5), &[1.0, -0.5, 2.0]);
+
+        let quarter_turn = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let mut values = Quat::IDENTITY.to_array().to_vec();
+        values.extend(quarter_turn.to_array());
+        let rotation = channel(
+            Property::Rotation,
+            Interpolation::Linear,
+            &[0.0, 1.0],
+            &values,
+        );
+        let eighth_turn = Quat::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        assert_close(&rotation.sample(0.5), &eighth_turn.to_array());
+    }
+
+    #[test]
+    fn cubic_spline_scales_tangents_by_key_spacing() {
+        // keys 2 s apart: in-tangent, value, out-tangent each
+        let weights = channel(
+            Property::MorphTargetWeights,
+            Interpolation::CubicSpline,
+            &[0.0, 2.0],
+            &[0.0, 0.0, 1.0, 0.0, 2.0, 0.0],
+        );
+        // halfway: 0.5 * p0 + 0.125 * 2 s * m0 + 0.5 * p1 - 0.125 * 2 s * m1
+        assert_close(&weights.sample(1.0), &[1.25]);
+        assert_close(&weights.sample(0.0), &[0.0]);
+        assert_close(&weights.sample(2.0), &[2.0]);
+    }
+
+    #[test]
+    fn sample_clamps_outside_the_keys() {
+        let linear = channel(
+            Property::MorphTargetWeights,
+            Interpolation::Linear,
+            &[1.0, 2.0],
+            &[3.0, 5.0],
+        );
+        assert_close(&linear.sample(0.0), &[3.0]);
+        assert_close(&linear.sample(7.0), &[5.0]);
+
+        // values, not tangents, of CUBICSPLINE keys
+        let cubic = channel(
+            Property::MorphTargetWeights,
+            Interpolation::CubicSpline,
+            &[1.0, 2.0],
+            &[9.0, 3.0, 9.0, 9.0, 5.0, 9.0],
+        );
+        assert_close(&cubic.sample(-1.0), &[3.0]);
+        assert_close(&cubic.sample(3.0), &[5.0]);
+    }
+
+    #[test]
+    fn step_time_wraps_when_looping() {
+        let (time, ended) = step_time(0.9, 0.3, 1.0, true);
+        assert!((time - 0.2).abs() < 1e-5 && !ended);
+        let (time, ended) = step_time(0.1, -0.3, 1.0, true);
+        assert!((time - 0.8).abs() < 1e-5 && !ended);
+    }
+
+    #[test]
+    fn step_time_stops_at_the_end_it_runs_into() {
+        assert_eq!(step_time(0.9, 0.3, 1.0, false), (1.0, true));
+        assert_eq!(step_time(0.1, -0.3, 1.0, false), (0.0, true));
+        assert_eq!(step_time(0.5, 0.25, 1.0, false), (0.75, false));
+        // a first frame without elapsed time doesn't end a clip that starts at 0
+        assert_eq!(step_time(0.0, 0.0, 1.0, false), (0.0, false));
+        assert_eq!(step_time(0.3, 0.1, 0.0, false), (0.0, true));
+        assert_eq!(step_time(0.3, 0.1, 0.0, true), (0.0, false));
+    }
+
+    #[test]
+    fn clip_ending_mid_fade_clears_the_fade() {
+        let clip = Clip {
+            name: None,
+            duration: 1.0,
+            channels: Vec::new(),
+        };
+        let mut player = AnimationPlayer {
+            rest: Vec::new(),
+            current: Some(ClipTime { clip: 1, time: 0.9 }),
+            fade: Some(Fade {
+                from: ClipTime { clip: 0, time: 0.5 },
+                elapsed: 0.0,
+                duration: 1.0,
+            }),
+            playing: true,
+            looping: false,
+            speed: 1.0,
+        };
+        player.advance(&[clip.clone(), clip], 0.2);
+        assert!(!player.playing);
+        assert!(player.fade.is_none());
+        assert_eq!(player.time(), 1.0);
+    }
+}
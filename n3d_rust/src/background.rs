@@ -0,0 +1,460 @@
+use crate::camera_math::Camera;
+use crate::custom_structs::CustomStructs::BackgroundUniform;
+use crate::ibl::{Environment, PREFILTER_MIPS};
+use crate::logger;
+use crate::renderer_error::RendererError;
+use wgpu::util::DeviceExt;
+
+// What is drawn behind the model. Colors are linear RGB.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackgroundMode {
+    Color([f32; 3]),
+    // vertical gradient from the top of the view to the bottom
+    Gradient { top: [f32; 3], bottom: [f32; 3] },
+    // clears to alpha 0 so the page shows through the canvas
+    Transparent,
+    // the environment cubemap, blur 0 (sharp) .. 1 (as soft as the roughest specular mip)
+    Skybox { blur: f32 },
+    // the image given to load_background_image, scaled to cover the view
+    Image,
+}
+
+impl Default for BackgroundMode {
+    fn default() -> Self {
+        BackgroundMode::Color([0.1, 0.1, 0.1])
+    }
+}
+
+impl BackgroundMode {
+    // `mode` in background_shader.wgsl, 0 when clearing is enough
+    fn shader_mode(&self) -> u32 {
+        match self {
+            BackgroundMode::Color(_) | BackgroundMode::Transparent => 0,
+            BackgroundMode::Gradient { .. } => 1,
+            BackgroundMode::Skybox { .. } => 2,
+            BackgroundMode::Image => 3,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl BackgroundMode {
+    // { mode: "color", color }, { mode: "gradient", top, bottom }, { mode: "transparent" },
+    // { mode: "skybox", blur }, { mode: "image" }. Colors are "#rrggbb" or sRGB [r, g, b] in 0..1.
+    pub fn from_js(value: &wasm_bindgen::JsValue) -> Result<BackgroundMode, RendererError> {
+        let get = |key: &str| js_sys::Reflect::get(value, &key.into()).unwrap_or_default();
+        let color = |key: &str| {
+            color_from_js(&get(key)).ok_or_else(|| {
+                RendererError::InvalidArgument(format!(
+                    "background {} must be \"#rrggbb\" or [r, g, b]",
+                    key
+                ))
+            })
+        };
+        match get("mode").as_string().as_deref() {
+            Some("color") => Ok(BackgroundMode::Color(color("color")?)),
+            Some("gradient") => Ok(BackgroundMode::Gradient {
+                top: color("top")?,
+                bottom: color("bottom")?,
+            }),
+            Some("transparent") => Ok(BackgroundMode::Transparent),
+            Some("skybox") => Ok(BackgroundMode::Skybox {
+                blur: get("blur").as_f64().unwrap_or(0.0) as f32,
+            }),
+            Some("image") => Ok(BackgroundMode::Image),
+            other => Err(RendererError::InvalidArgument(format!(
+                "unknown background mode {:?}",
+                other.unwrap_or("undefined")
+            ))),
+        }
+    }
+}
+
+// "#rrggbb" or an sRGB [r, g, b] array in 0..1, as linear RGB
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn color_from_js(value: &wasm_bindgen::JsValue) -> Option<[f32; 3]> {
+    if let Some(hex) = value.as_string() {
+        return parse_hex_color(&hex);
+    }
+    if !js_sys::Array::is_array(value) {
+        return None;
+    }
+    let channels: Vec<f32> = js_sys::Array::from(value)
+        .iter()
+        .filter_map(|c| c.as_f64())
+        .map(|c| srgb_to_linear(c.clamp(0.0, 1.0) as f32))
+        .collect();
+    <[f32; 3]>::try_from(channels).ok()
+}
+
+// "#rrggbb" or "rrggbb" in sRGB, as linear RGB
+pub fn parse_hex_color(hex: &str) -> Option<[f32; 3]> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+            .ok()
+            .map(|c| srgb_to_linear(c as f32 / 255.0))
+    };
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Clears the frame and, for gradients, skyboxes and images, draws a fullscreen
+// triangle before the model (depth test off, so it never hides geometry). The skybox is
+// drawn into the HDR target so it is tone mapped like the model; colors, gradients and
+// images are display referred and go straight into the surface, under the tone mapped frame.
+pub struct Background {
+    pub mode: BackgroundMode,
+    layout: wgpu::BindGroupLayout,
+    // into the surface, for gradients and images
+    pipeline: wgpu::RenderPipeline,
+    // into the HDR target, for the skybox
+    hdr_pipeline: wgpu::RenderPipeline,
+    // the surface is not sRGB, so colors are encoded by hand
+    encode_srgb: bool,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    // source cubemap of the current environment for a sharp skybox, and its prefiltered
+    // specular mips for a blurred one
+    environment_view: wgpu::TextureView,
+    prefiltered_view: wgpu::TextureView,
+    // background image and its width / height; a 1x1 placeholder until one is loaded
+    image: (wgpu::TextureView, f32),
+    has_image: bool,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Background {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        hdr_format: wgpu::TextureFormat,
+        sample_count: u32,
+        environment: &Environment,
+    ) -> Background {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Background Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("background_shader.wgsl").into()),
+        });
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Background BGL"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1, wgpu::TextureViewDimension::Cube),
+                texture_entry(2, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(4, wgpu::TextureViewDimension::Cube),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Background Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label, format, depth_stencil, count| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil,
+                multisample: wgpu::MultisampleState {
+                    count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+        let pipeline = create_pipeline("Background Pipeline", format, None, 1);
+        // shares the HDR pass with the model, so it has to match its depth and MSAA attachments
+        let hdr_pipeline = create_pipeline(
+            "Background HDR Pipeline",
+            hdr_format,
+            Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth24Plus,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            sample_count,
+        );
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Background Uniform"),
+            size: std::mem::size_of::<BackgroundUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Background Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let placeholder = create_image_texture(device, queue, 1, 1, &[0, 0, 0, 255])
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let environment_view = environment.environment_view.clone();
+        let prefiltered_view = environment.prefiltered_view.clone();
+        let bind_group = create_bind_group(
+            device,
+            &layout,
+            &uniform_buffer,
+            (&environment_view, &prefiltered_view),
+            &placeholder,
+            &sampler,
+        );
+        Background {
+            mode: BackgroundMode::default(),
+            layout,
+            pipeline,
+            hdr_pipeline,
+            encode_srgb: !format.is_srgb(),
+            uniform_buffer,
+            sampler,
+            environment_view,
+            prefiltered_view,
+            image: (placeholder, 1.0),
+            has_image: false,
+            bind_group,
+        }
+    }
+
+    pub fn has_image(&self) -> bool {
+        self.has_image
+    }
+
+    // Follows a newly baked environment for the skybox
+    pub fn set_environment(&mut self, device: &wgpu::Device, environment: &Environment) {
+        self.environment_view = environment.environment_view.clone();
+        self.prefiltered_view = environment.prefiltered_view.clone();
+        self.rebuild_bind_group(device);
+    }
+
+    // Decodes a PNG or JPEG; images larger than the device allows are scaled down
+    pub fn load_image(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+    ) -> Result<(), RendererError> {
+        let mut image = image::load_from_memory(bytes).map_err(|e| {
+            RendererError::InvalidArgument(format!("could not decode background image: {}", e))
+        })?;
+        let max_size = device.limits().max_texture_dimension_2d;
+        if image.width() > max_size || image.height() > max_size {
+            image = image.resize(max_size, max_size, image::imageops::FilterType::Triangle);
+        }
+        let rgba = image.to_rgba8();
+        let (width, height) = rgba.dimensions();
+        let view = create_image_texture(device, queue, width, height, &rgba)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.image = (view, width as f32 / height as f32);
+        self.has_image = true;
+        self.rebuild_bind_group(device);
+        logger::log(&format!("background image loaded ({}x{})", width, height));
+        Ok(())
+    }
+
+    fn rebuild_bind_group(&mut self, device: &wgpu::Device) {
+        self.bind_group = create_bind_group(
+            device,
+            &self.layout,
+            &self.uniform_buffer,
+            (&self.environment_view, &self.prefiltered_view),
+            &self.image.0,
+            &self.sampler,
+        );
+    }
+
+    // Color the surface is cleared to; gradients and images cover it completely
+    pub fn clear_color(&self) -> wgpu::Color {
+        let encode = |c: f32| {
+            if self.encode_srgb {
+                linear_to_srgb(c.clamp(0.0, 1.0)) as f64
+            } else {
+                c as f64
+            }
+        };
+        match &self.mode {
+            BackgroundMode::Color([r, g, b]) => wgpu::Color {
+                r: encode(*r),
+                g: encode(*g),
+                b: encode(*b),
+                a: 1.0,
+            },
+            BackgroundMode::Transparent => wgpu::Color::TRANSPARENT,
+            _ => wgpu::Color::BLACK,
+        }
+    }
+
+    // Uploads the camera rays and the current mode's parameters
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera) {
+        let (top, bottom) = match &self.mode {
+            BackgroundMode::Gradient { top, bottom } => (*top, *bottom),
+            _ => ([0.0; 3], [0.0; 3]),
+        };
+        let blur = match &self.mode {
+            BackgroundMode::Skybox { blur } => blur.clamp(0.0, 1.0),
+            _ => 0.0,
+        };
+        let uniform = BackgroundUniform {
+            inv_view_proj: camera.view_proj_matrix().inverse().to_cols_array_2d(),
+            camera_pos: camera.position.to_array(),
+            mode: self.mode.shader_mode(),
+            top: [top[0], top[1], top[2], 1.0],
+            bottom: [bottom[0], bottom[1], bottom[2], 1.0],
+            lod: blur * (PREFILTER_MIPS - 1) as f32,
+            view_aspect: camera.aspect,
+            image_aspect: self.image.1,
+            // the skybox is encoded by the tone mapping pass instead
+            encode_srgb: (self.encode_srgb && self.mode.shader_mode() != 2) as u32,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    // Records a gradient or image into the surface pass; no-op for the other modes
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        if !matches!(self.mode.shader_mode(), 1 | 3) {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, Some(&self.bind_group), &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Records the skybox into the HDR pass, before the model; no-op for the other modes
+    pub fn draw_hdr(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.mode.shader_mode() != 2 {
+            return;
+        }
+        render_pass.set_pipeline(&self.hdr_pipeline);
+        render_pass.set_bind_group(0, Some(&self.bind_group), &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_image_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+) -> wgpu::Texture {
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some("Background Image"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        rgba,
+    )
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    (environment_view, prefiltered_view): (&wgpu::TextureView, &wgpu::TextureView),
+    image_view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Background BG"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(environment_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(image_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(prefiltered_view),
+            },
+        ],
+    })
+}
@@ -0,0 +1,76 @@
+// Fullscreen pass drawn before the model when the background is more than a clear color.
+// The skybox goes into the HDR target and is tone mapped with the model, gradients and
+// images are drawn display referred, under the tone mapped frame (see background.rs).
+
+struct BackgroundUniform {
+    invViewProj : mat4x4<f32>,
+    cameraPos   : vec3<f32>,
+    mode        : u32,        // 1 gradient, 2 skybox, 3 image
+    top         : vec4<f32>,  // linear RGBA
+    bottom      : vec4<f32>,
+    lod         : f32,        // prefiltered mip for the skybox blur, 0 for the sharp source
+    viewAspect  : f32,
+    imageAspect : f32,
+    encodeSrgb  : u32,        // 1 when the target is not an sRGB format
+};
+@group(0) @binding(0) var<uniform> background : BackgroundUniform;
+@group(0) @binding(1) var environmentMap : texture_cube<f32>;
+@group(0) @binding(2) var backgroundImage : texture_2d<f32>;
+@group(0) @binding(3) var backgroundSampler : sampler;
+@group(0) @binding(4) var prefilteredMap : texture_cube<f32>;
+
+struct VSOut {
+    @builtin(position) position : vec4<f32>,
+    @location(0) uv : vec2<f32>,   // 0..1, origin top-left
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> VSOut {
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out : VSOut;
+    out.position = vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    return out;
+}
+
+fn linear_to_srgb(c : vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in : VSOut) -> @location(0) vec4<f32> {
+    let color = background_color(in);
+    if (background.encodeSrgb != 0u) {
+        return vec4<f32>(linear_to_srgb(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0))), color.a);
+    }
+    return color;
+}
+
+fn background_color(in : VSOut) -> vec4<f32> {
+    switch background.mode {
+        case 1u: {
+            return mix(background.top, background.bottom, in.uv.y);
+        }
+        case 2u: {
+            // world ray through this pixel on the far plane
+            let ndc = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0);
+            let far = background.invViewProj * vec4<f32>(ndc, 1.0, 1.0);
+            let dir = normalize(far.xyz / far.w - background.cameraPos);
+            // the full resolution source up to the first blurred mip, then the prefiltered mips
+            let sharp = textureSampleLevel(environmentMap, backgroundSampler, dir, 0.0).rgb;
+            let blurred = textureSampleLevel(prefilteredMap, backgroundSampler, dir, max(background.lod, 1.0)).rgb;
+            let color = mix(sharp, blurred, clamp(background.lod, 0.0, 1.0));
+            return vec4<f32>(color, 1.0);
+        }
+        default: {
+            // image scaled to cover the view, cropping the longer side around the centre
+            var uv = in.uv;
+            if (background.viewAspect > background.imageAspect) {
+                uv.y = 0.5 + (uv.y - 0.5) * background.imageAspect / background.viewAspect;
+            } else {
+                uv.x = 0.5 + (uv.x - 0.5) * background.viewAspect / background.imageAspect;
+            }
+            return vec4<f32>(textureSampleLevel(backgroundImage, backgroundSampler, uv, 0.0).rgb, 1.0);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Renders a model headlessly into a PNG:
+// `cargo run --bin n3d_thumbnail -- model.glb out.png [--size 256] [--env sky.hdr] [--fallback]
+//  [--background transparent|skybox|#rrggbb|backdrop.jpg] [--ground] [--no-shadows]
+//  [--tone-mapping neutral|aces|agx|reinhard|none] [--exposure 1.0] [--msaa 1|4]`
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    use renderer::background::{parse_hex_color, BackgroundMode};
+    use renderer::offscreen::HeadlessRenderer;
+    use renderer::renderer_options::RendererOptions;
+    use renderer::shadows::ShadowSettings;
+    use renderer::tone_mapping::ToneMapping;
+
+    let mut options = RendererOptions::default();
+    let mut size = 256;
+    let mut environment = None;
+    let mut background = None;
+    let mut shadows = ShadowSettings::default();
+    let mut tone_mapping = ToneMapping::default();
+    let mut exposure = 1.0;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fallback" => options.force_fallback_adapter = true,
+            "--env" => environment = args.next(),
+            "--background" => background = args.next(),
+            "--ground" => shadows.ground = true,
+            "--no-shadows" => shadows.enabled = false,
+            "--tone-mapping" => match args.next().as_deref().and_then(ToneMapping::from_name) {
+                Some(parsed) => tone_mapping = parsed,
+                None => fail("--tone-mapping expects neutral, aces, agx, reinhard or none"),
+            },
+            "--exposure" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(parsed) => exposure = parsed,
+                None => fail("--exposure expects a number"),
+            },
+            "--msaa" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(samples) => options.msaa_samples = samples,
+                None => fail("--msaa expects 1 or 4"),
+            },
+            "--size" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(parsed) => size = parsed,
+                None => fail("--size expects a number of pixels"),
+            },
+            _ => paths.push(arg),
+        }
+    }
+    let [model_path, png_path] = paths.as_slice() else {
+        fail(
+            "usage: n3d_thumbnail <model.glb> <out.png> [--size N] [--env sky.hdr] [--fallback] \
+             [--background transparent|skybox|#rrggbb|image] [--ground] [--no-shadows] \
+             [--tone-mapping neutral|aces|agx|reinhard|none] [--exposure X] [--msaa 1|4]",
+        );
+    };
+    let bytes = std::fs::read(model_path)
+        .unwrap_or_else(|e| fail(&format!("could not read {}: {}", model_path, e)));
+    let hdr = environment.map(|path| {
+        std::fs::read(&path).unwrap_or_else(|e| fail(&format!("could not read {}: {}", path, e)))
+    });
+    // anything that is not a mode or a color is read as a background image
+    let (background, background_image) = match background.as_deref() {
+        None => (None, None),
+        Some("transparent") => (Some(BackgroundMode::Transparent), None),
+        Some("skybox") => (Some(BackgroundMode::Skybox { blur: 0.5 }), None),
+        Some(value) => match parse_hex_color(value) {
+            Some(color) => (Some(BackgroundMode::Color(color)), None),
+            None => (
+                None,
+                Some(
+                    std::fs::read(value)
+                        .unwrap_or_else(|e| fail(&format!("could not read {}: {}", value, e))),
+                ),
+            ),
+        },
+    };
+    let png = pollster::block_on(async {
+        let renderer = HeadlessRenderer::new(size, size, &options).await?;
+        if let Some(hdr) = &hdr {
+            renderer.load_environment(hdr)?;
+        }
+        if let Some(mode) = background {
+            renderer.set_background(mode)?;
+        }
+        if let Some(image) = &background_image {
+            renderer.load_background_image(image)?;
+        }
+        renderer.set_shadows(shadows);
+        renderer.set_tone_mapping(tone_mapping);
+        renderer.set_exposure(exposure);
+        renderer.load_model(&bytes, None).await?;
+        renderer.render()?.to_png()
+    })
+    .unwrap_or_else(|e| fail(&e.to_string()));
+    if let Err(e) = std::fs::write(png_path, png) {
+        fail(&format!("could not write {}: {}", png_path, e));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -0,0 +1,40 @@
+// Native desktop viewer: `cargo run --bin n3d_viewer -- [model.glb] [--env sky.hdr] [--fallback]`
+// --fallback asks for a software adapter (lavapipe / llvmpipe)
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    let mut path = String::from("../n3d_pure/models/DamagedHelmet.glb");
+    let mut options = renderer::renderer_options::RendererOptions::default();
+    let mut environment_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fallback" => options.force_fallback_adapter = true,
+            "--env" => environment_path = args.next(),
+            "--msaa" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(samples) => options.msaa_samples = samples,
+                None => {
+                    eprintln!("--msaa expects 1 or 4");
+                    std::process::exit(1);
+                }
+            },
+            _ => path = arg,
+        }
+    }
+    let bytes = read(&path);
+    let environment = environment_path.as_deref().map(read);
+    if let Err(e) = renderer::native::run(bytes, environment, options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("could not read {}: {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -1,5 +1,8 @@
 use glam::{Mat4, Vec3};
 
+// keeps orbit pitch away from the poles where look_at flips
+const MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
 pub struct Camera {
     pub position: Vec3,
     pub target: Vec3,
@@ -37,14 +40,49 @@ impl Camera {
         self.position += forward * delta;
     }
 
+    // Orbit around target: yaw about the up axis, pitch towards/away from it (radians)
+    pub fn orbit(&mut self, delta_yaw: f32, delta_pitch: f32) {
+        let offset = self.position - self.target;
+        let distance = offset.length();
+        let yaw = offset.x.atan2(offset.z) - delta_yaw;
+        let pitch = ((offset.y / distance).clamp(-1.0, 1.0).asin() + delta_pitch)
+            .clamp(-MAX_PITCH, MAX_PITCH);
+        self.position = self.target
+            + distance
+                * Vec3::new(
+                    pitch.cos() * yaw.sin(),
+                    pitch.sin(),
+                    pitch.cos() * yaw.cos(),
+                );
+    }
+
+    // Dolly towards (negative delta) or away from target, scaling the orbit distance
+    pub fn dolly(&mut self, delta: f32) {
+        let offset = self.position - self.target;
+        let distance = (offset.length() * delta.exp()).clamp(self.znear * 2.0, self.zfar * 0.5);
+        self.position = self.target + offset.normalize() * distance;
+    }
+
+    // Pan target and eye together; deltas are in NDC units of the current view
+    pub fn pan(&mut self, delta_x: f32, delta_y: f32) {
+        let forward = self.target - self.position;
+        let distance = forward.length();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward).normalize();
+        let half_height = distance * (self.fov_y * 0.5).tan();
+        let offset = right * (-delta_x * half_height * self.aspect) + up * (-delta_y * half_height);
+        self.position += offset;
+        self.target += offset;
+    }
+
     // View matrix (world → camera space)
     pub fn view_matrix(&self) -> Mat4 {
         Mat4::look_at_rh(self.position, self.target, self.up)
     }
 
-    // Projection matrix (camera → clip space)
+    // Projection matrix (camera → clip space, wgpu depth range 0..1)
     pub fn projection_matrix(&self) -> Mat4 {
-        Mat4::perspective_rh_gl(self.fov_y, self.aspect, self.znear, self.zfar)
+        Mat4::perspective_rh(self.fov_y, self.aspect, self.znear, self.zfar)
     }
 
     // Combined VP matrix
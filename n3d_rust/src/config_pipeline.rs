@@ -1,6 +1,6 @@
 use super::custom_structs::CustomStructs;
+use crate::logger;
 use wasm_bindgen::JsValue;
-use web_sys::console;
 use wgpu::util::DeviceExt;
 use wgpu::ShaderModule;
 pub struct ConfigPipeline {}
@@ -17,7 +17,7 @@ impl ConfigPipeline {
         device: wgpu::Device,
         surface_format: wgpu::TextureFormat,
     ) -> wgpu::RenderPipeline {
-        console::log_1(&"config_basic starts".into());
+        logger::log("config_basic starts");
 
         // -----------------------------
         // Group 0: uniform buffer (camera + model)
@@ -100,37 +100,43 @@ impl ConfigPipeline {
             step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
                 wgpu::VertexAttribute {
-                    offset: 0,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, position)
+                        as wgpu::BufferAddress,
                     shader_location: 0, // position
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: 12,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, normal)
+                        as wgpu::BufferAddress,
                     shader_location: 1, // normal
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: 24,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, tangent)
+                        as wgpu::BufferAddress,
                     shader_location: 2, // tangent
                     format: wgpu::VertexFormat::Float32x4,
                 },
                 wgpu::VertexAttribute {
-                    offset: 40,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, uv) as wgpu::BufferAddress,
                     shader_location: 3, // uv0
                     format: wgpu::VertexFormat::Float32x2,
                 },
                 wgpu::VertexAttribute {
-                    offset: 48,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, color)
+                        as wgpu::BufferAddress,
                     shader_location: 4, // color
                     format: wgpu::VertexFormat::Float32x4,
                 },
                 wgpu::VertexAttribute {
-                    offset: 64,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, joints)
+                        as wgpu::BufferAddress,
                     shader_location: 5, // joints
                     format: wgpu::VertexFormat::Uint32x4,
                 },
                 wgpu::VertexAttribute {
-                    offset: 80,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, weights)
+                        as wgpu::BufferAddress,
                     shader_location: 6, // weights
                     format: wgpu::VertexFormat::Float32x4,
                 },
@@ -180,7 +186,7 @@ impl ConfigPipeline {
             cache: None,
         });
 
-        console::log_1(&"config_basic ends".into());
+        logger::log("config_basic ends");
         render_pipeline
     }
 }
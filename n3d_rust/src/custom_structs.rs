@@ -5,6 +5,124 @@ pub mod CustomStructs {
         pub vertex_buffer: wgpu::Buffer,
         pub indice_buffer: wgpu::Buffer,
         pub indice_count: u32,
+        pub primitives: Vec<Primitive>,
+        pub scene: crate::scene_graph::SceneGraph,
+        pub draws: Vec<Draw>,
+        // KHR_lights_punctual lights as (node, light in node space)
+        pub lights: Vec<(usize, crate::lights::Light)>,
+        // indexed like `document.skins()`, JointsUniform slot i belongs to skin i
+        pub skins: Vec<crate::skinning::Skin>,
+        // indexed like `document.animations()`
+        pub animations: Vec<crate::animation::Clip>,
+        // indexed like `document.meshes()`
+        pub meshes: Vec<Mesh>,
+        // morph target deltas of every primitive (see morph_targets.rs)
+        pub morph_deltas: wgpu::TextureView,
+    }
+    impl Model {
+        // Mesh to world matrix of a draw; skinned draws are placed by their joints alone
+        pub fn draw_matrix(&self, draw: &Draw) -> glam::Mat4 {
+            match draw.skin {
+                Some(_) => glam::Mat4::IDENTITY,
+                None => self.scene.world[draw.node],
+            }
+        }
+
+        // Dynamic offsets of draw i into the camera bind group: its CameraUniform slot and its
+        // skin's JointsUniform slot (slot 0 for unskinned draws, which don't read it)
+        pub fn dynamic_offsets(&self, i: usize, camera_stride: u64) -> [wgpu::DynamicOffset; 2] {
+            let joints_stride = std::mem::size_of::<JointsUniform>() as u64;
+            let skin = self.draws[i].skin.unwrap_or(0) as u64;
+            [
+                (i as u64 * camera_stride) as wgpu::DynamicOffset,
+                (skin * joints_stride) as wgpu::DynamicOffset,
+            ]
+        }
+
+        // World space bounding box of a draw in the current pose. A skinned vertex is a weighted
+        // blend of its joints' matrices applied to it, so the mesh space box moved by every joint
+        // of the skin contains it.
+        pub fn draw_bounds(&self, draw: &Draw) -> (glam::Vec3, glam::Vec3) {
+            let (min, max) = self.primitives[draw.primitive].bounds;
+            let mut matrices: Vec<glam::Mat4> = match draw.skin.and_then(|s| self.skins.get(s)) {
+                Some(skin) => skin.joint_matrices(&self.scene).collect(),
+                None => Vec::new(),
+            };
+            if matrices.is_empty() {
+                matrices.push(self.draw_matrix(draw));
+            }
+            let mut bounds = (glam::Vec3::INFINITY, glam::Vec3::NEG_INFINITY);
+            for matrix in matrices {
+                for i in 0..8 {
+                    let corner = glam::Vec3::new(
+                        if i & 1 == 0 { min.x } else { max.x },
+                        if i & 2 == 0 { min.y } else { max.y },
+                        if i & 4 == 0 { min.z } else { max.z },
+                    );
+                    let p = matrix.transform_point3(corner);
+                    bounds = (bounds.0.min(p), bounds.1.max(p));
+                }
+            }
+            bounds
+        }
+
+        // World space bounding box of every draw, None for an empty scene
+        pub fn world_bounds(&self) -> Option<(glam::Vec3, glam::Vec3)> {
+            self.draws
+                .iter()
+                .map(|draw| self.draw_bounds(draw))
+                .reduce(|(lo, hi), (min, max)| (lo.min(min), hi.max(max)))
+        }
+
+        // Draw indices in the order to record them: opaque and masked draws as listed, then
+        // blended ones back to front by view space depth of their bounds' center
+        pub fn draw_order(&self, view: glam::Mat4) -> Vec<usize> {
+            let (mut order, mut blended): (Vec<usize>, Vec<usize>) = (0..self.draws.len())
+                .partition(|&i| self.primitives[self.draws[i].primitive].alpha_mode != ALPHA_BLEND);
+            let depth = |i: usize| {
+                let (min, max) = self.draw_bounds(&self.draws[i]);
+                let center = (min + max) * 0.5;
+                // the camera looks down -z
+                view.transform_point3(center).z
+            };
+            blended.sort_by(|&a, &b| depth(a).total_cmp(&depth(b)));
+            order.extend(blended);
+            order
+        }
+    }
+    // one glTF primitive: its range inside the shared index buffer and its material
+    #[derive(Debug, Clone, Copy)]
+    pub struct Primitive {
+        pub mesh_index: usize,
+        pub first_index: u32,
+        pub index_count: u32,
+        pub material_index: Option<usize>,
+        // POSITION accessor min / max in mesh space
+        pub bounds: (glam::Vec3, glam::Vec3),
+        // the material's ALPHA_OPAQUE / ALPHA_MASK / ALPHA_BLEND and doubleSided
+        pub alpha_mode: u32,
+        pub double_sided: bool,
+        // vertex range inside the shared vertex buffer
+        pub first_vertex: u32,
+        pub vertex_count: u32,
+        // morph targets and the texel their deltas start at in Model::morph_deltas
+        pub morph_targets: u32,
+        pub morph_base: u32,
+    }
+    // what JS needs to address a glTF mesh
+    #[derive(Debug, Clone)]
+    pub struct Mesh {
+        pub name: Option<String>,
+        // most targets of any of its primitives
+        pub morph_targets: usize,
+    }
+    // one primitive placed by one scene node; draw i uses CameraUniform slot i
+    #[derive(Debug, Clone, Copy)]
+    pub struct Draw {
+        pub node: usize,
+        pub primitive: usize,
+        // the node's skin, its joints replace the node transform
+        pub skin: Option<usize>,
     }
     #[repr(C)]
     #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -23,25 +141,136 @@ pub mod CustomStructs {
         pub view_proj: [[f32; 4]; 4],
         pub model: [[f32; 4]; 4],
         pub camera_pos: [f32; 3],
-        pub _pad: f32, // padding for 16-byte alignment
+        // 1 when the draw is skinned by the bound JointsUniform
+        pub skinned: u32,
+        // the first `morph_count` slots are the draw's active morph targets (morph_targets.rs)
+        pub morph_weights: [[f32; 4]; 2],
+        pub morph_indices: [[u32; 4]; 2],
+        // Primitive::morph_base, first_vertex and vertex_count
+        pub morph_base: u32,
+        pub morph_vertex_start: u32,
+        pub morph_vertex_count: u32,
+        pub morph_count: u32,
+    }
+    impl CameraUniform {
+        // distance between per-draw slots, rounded up for dynamic offsets
+        pub fn aligned_size(device: &wgpu::Device) -> u64 {
+            let size = std::mem::size_of::<CameraUniform>() as u64;
+            let align = device.limits().min_uniform_buffer_offset_alignment as u64;
+            size.div_ceil(align) * align
+        }
     }
+    // Joints in vertex_shader.wgsl and shadow_shader.wgsl, one slot per skin (see skinning.rs)
     #[repr(C)]
-    #[derive(Copy, Clone, bytemuck:: Pod, bytemuck::Zeroable)]
-    pub struct LightUniform {
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct JointsUniform {
+        pub matrices: [[[f32; 4]; 4]; crate::skinning::MAX_JOINTS],
+    }
+    // PunctualLight in pbr_fragment_shader.wgsl (see lights.rs)
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct PunctualLightUniform {
+        pub position: [f32; 3],
+        // 0 = no cutoff, inverse square falloff only
+        pub range: f32,
         pub direction: [f32; 3],
-        pub _pad1: f32,
+        // 0 directional, 1 point, 2 spot
+        pub kind: u32,
         pub color: [f32; 3],
-        pub _pad2: f32,
+        pub intensity: f32,
+        // spot cone, see KHR_lights_punctual's reference implementation
+        pub angle_scale: f32,
+        pub angle_offset: f32,
+        pub _pad: [f32; 2],
     }
+    // group 2 of the PBR pipeline, the first `count` lights are used
     #[repr(C)]
     #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-    pub struct Material {
-        pub base_color_factor: [f32; 4],
-        pub alpha_cutoff: f32,
-        pub alpha_mode: u32,
-        pub double_sided: u32,
-        // padding to 16-byte align struct
-        pub _padding: [u32; 1],
+    pub struct LightsUniform {
+        pub lights: [PunctualLightUniform; crate::lights::MAX_LIGHTS],
+        pub count: u32,
+        pub _pad: [u32; 3],
+    }
+    // Shadow in pbr_fragment_shader.wgsl, shadow_shader.wgsl and ground_shader.wgsl (see shadows.rs)
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct ShadowUniform {
+        pub light_view_proj: [[f32; 4]; 4],
+        // towards the key light
+        pub to_light: [f32; 3],
+        // depth bias in light clip space
+        pub bias: f32,
+        // offset along the normal in world units
+        pub normal_bias: f32,
+        // PCF kernel radius in texels
+        pub pcf_radius: i32,
+        pub enabled: u32,
+        pub texel_size: f32,
+        // share of the environment light removed in shadow when the environment is the key light
+        pub strength: f32,
+        // slot of the shadowed light in LightsUniform, -1 = the environment
+        pub key_light: i32,
+        pub _pad: [f32; 2],
+    }
+    // Ground in ground_shader.wgsl, a square shadow catcher on the floor under the model
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct GroundUniform {
+        pub view_proj: [[f32; 4]; 4],
+        pub center: [f32; 3],
+        pub half_size: f32,
+        pub opacity: f32,
+        pub _pad: [f32; 3],
+    }
+    // group 3 of the PBR pipeline, next to the IBL maps (see ibl.rs)
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct EnvironmentUniform {
+        pub intensity: f32,
+        // highest mip of the prefiltered specular cubemap (roughness 1)
+        pub max_lod: f32,
+        pub _pad: [f32; 2],
+    }
+    // BakeParams in ibl_shader.wgsl, one slot per IBL bake pass
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct IblBakeParams {
+        pub face: u32,
+        pub roughness: f32,
+        pub source_lod: f32,
+        pub source_size: f32,
+        pub sample_count: u32,
+        pub _pad: [u32; 3],
+    }
+    // BackgroundUniform in background_shader.wgsl (see background.rs)
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct BackgroundUniform {
+        // turns clip space back into world rays for the skybox
+        pub inv_view_proj: [[f32; 4]; 4],
+        pub camera_pos: [f32; 3],
+        // 0 clear color only, 1 gradient, 2 skybox, 3 image
+        pub mode: u32,
+        // linear RGBA
+        pub top: [f32; 4],
+        pub bottom: [f32; 4],
+        pub lod: f32,
+        pub view_aspect: f32,
+        pub image_aspect: f32,
+        // 1 when drawing into a non-sRGB surface
+        pub encode_srgb: u32,
+    }
+    // ToneMapping in tone_mapping_shader.wgsl (see tone_mapping.rs)
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct ToneMappingUniform {
+        // linear multiplier on the HDR color before tone mapping
+        pub exposure: f32,
+        // ToneMapping::shader_mode
+        pub mode: u32,
+        // 1 when the surface format does not encode sRGB itself
+        pub encode_srgb: u32,
+        pub _pad: f32,
     }
     #[repr(C)]
     #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -51,7 +280,7 @@ pub mod CustomStructs {
         pub metallic_factor: f32,        // default 1.0
         pub roughness_factor: f32,       // default 1.0
 
-        // texture indices into your Texture struct arrays
+        // glTF texture indices into the Texture struct arrays (NO_TEXTURE if unused)
         pub base_color_texture: u32,
         pub metallic_roughness_texture: u32,
         pub normal_texture: u32,
@@ -61,12 +290,48 @@ pub mod CustomStructs {
         pub alpha_cutoff: f32,
         pub alpha_mode: u32,
         pub double_sided: u32,
-        pub _padding: [u32; 1],
+        pub normal_scale: f32, // normalTexture.scale, default 1.0
+        // WGSL rounds the struct up to 16 bytes
+        pub _padding: [u32; 2],
+    }
+
+    // MaterialPBR::alpha_mode values
+    pub const ALPHA_OPAQUE: u32 = 0;
+    pub const ALPHA_MASK: u32 = 1;
+    pub const ALPHA_BLEND: u32 = 2;
+
+    // slot of a primitive's pipeline in ModelResources::pipelines
+    pub fn pipeline_index(alpha_mode: u32, double_sided: bool) -> usize {
+        alpha_mode.min(ALPHA_BLEND) as usize * 2 + double_sided as usize
     }
 
+    // GPU state kept alive between frames once a model has been loaded
+    pub struct ModelResources {
+        pub model: Model,
+        // poses `model.scene` from `model.animations`
+        pub animation: crate::animation::AnimationPlayer,
+        // one PBR pipeline per alpha mode, back face culled and double-sided (pipeline_index)
+        pub pipelines: Vec<wgpu::RenderPipeline>,
+        pub camera_bind_group: wgpu::BindGroup,
+        pub material_bind_groups: Vec<wgpu::BindGroup>,
+        pub light_bind_group: wgpu::BindGroup,
+        pub light_buffer: wgpu::Buffer,
+        pub camera_buffer: wgpu::Buffer,
+        // JointsUniform per skin, bound next to the camera uniform
+        pub joints_buffer: wgpu::Buffer,
+        // depth-only pass from the key light (see shadows.rs)
+        pub shadow_pipeline: wgpu::RenderPipeline,
+        pub depth_texture: wgpu::TextureView,
+    }
+
+    // marks a MaterialPBR texture slot that the glTF material leaves empty
+    pub const NO_TEXTURE: u32 = u32::MAX;
+
+    // one entry per glTF texture, indexed by `texture().index()`; a view is None when no
+    // material samples the texture in that color space
     pub struct Texture {
-        pub textures: Vec<wgpu::Texture>,
-        pub views: Vec<wgpu::TextureView>,
+        pub srgb_views: Vec<Option<wgpu::TextureView>>,
+        pub linear_views: Vec<Option<wgpu::TextureView>>,
         pub samplers: Vec<wgpu::Sampler>,
     }
 }
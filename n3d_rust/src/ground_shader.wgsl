@@ -0,0 +1,79 @@
+// Shadow catcher: a floor square under the model that is invisible except where the
+// shadow map says the key light is blocked (see shadows.rs).
+
+struct Ground {
+    viewProj : mat4x4<f32>,
+    center   : vec3<f32>,   // centre of the square, y = floor height
+    halfSize : f32,
+    opacity  : f32,
+    _pad0    : f32,
+    _pad1    : f32,
+    _pad2    : f32,
+};
+@group(0) @binding(0) var<uniform> ground : Ground;
+
+struct Shadow {
+    lightViewProj : mat4x4<f32>,
+    toLight       : vec3<f32>,
+    bias          : f32,
+    normalBias    : f32,
+    pcfRadius     : i32,
+    enabled       : u32,
+    texelSize     : f32,
+    strength      : f32,
+    keyLight      : i32,
+    _pad          : vec2<f32>,
+};
+@group(0) @binding(1) var<uniform> shadow : Shadow;
+@group(0) @binding(2) var shadowMap : texture_depth_2d;
+@group(0) @binding(3) var shadowSampler : sampler_comparison;
+
+struct VSOut {
+    @builtin(position) position : vec4<f32>,
+    @location(0) world : vec3<f32>,
+    @location(1) local : vec2<f32>,   // -1..1 across the square
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> VSOut {
+    // two triangles
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let local = corners[index];
+    let world = ground.center + vec3<f32>(local.x, 0.0, local.y) * ground.halfSize;
+    var out : VSOut;
+    out.position = ground.viewProj * vec4<f32>(world, 1.0);
+    out.world = world;
+    out.local = local;
+    return out;
+}
+
+// 1 = lit, 0 = fully shadowed; same filtering as pbr_fragment_shader.wgsl
+fn shadow_visibility(position : vec3<f32>, normal : vec3<f32>) -> f32 {
+    let clip = shadow.lightViewProj * vec4<f32>(position + normal * shadow.normalBias, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    var lit = 0.0;
+    var taps = 0.0;
+    for (var y = -shadow.pcfRadius; y <= shadow.pcfRadius; y++) {
+        for (var x = -shadow.pcfRadius; x <= shadow.pcfRadius; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texelSize;
+            lit += textureSampleCompareLevel(shadowMap, shadowSampler, uv + offset, ndc.z - shadow.bias);
+            taps += 1.0;
+        }
+    }
+    return lit / taps;
+}
+
+@fragment
+fn fs_main(in : VSOut) -> @location(0) vec4<f32> {
+    // fade out towards the edges so the square itself never shows
+    let fade = 1.0 - smoothstep(0.6, 1.0, length(in.local));
+    let darkness = (1.0 - shadow_visibility(in.world, vec3<f32>(0.0, 1.0, 0.0))) * ground.opacity * fade;
+    return vec4<f32>(0.0, 0.0, 0.0, darkness);
+}
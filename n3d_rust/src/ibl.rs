@@ -0,0 +1,642 @@
+use crate::custom_structs::CustomStructs::{EnvironmentUniform, IblBakeParams};
+use crate::logger;
+use crate::renderer_error::RendererError;
+use wgpu::util::DeviceExt;
+
+// Face sizes of the baked cubemaps
+pub const ENVIRONMENT_SIZE: u32 = 256;
+pub const IRRADIANCE_SIZE: u32 = 32;
+pub const PREFILTER_SIZE: u32 = 128;
+// roughness 0, 0.25, .. 1 in the prefiltered specular mips
+pub const PREFILTER_MIPS: u32 = 5;
+pub const BRDF_LUT_SIZE: u32 = 128;
+const IBL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// phi steps of the irradiance convolution (theta gets a quarter of them)
+const IRRADIANCE_STEPS: u32 = 64;
+const PREFILTER_SAMPLES: u32 = 512;
+
+// Linear HDR pixels, RGBA f32, rows top to bottom (equirectangular for environments)
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<f32>,
+}
+
+impl HdrImage {
+    // Radiance RGBE (.hdr)
+    pub fn decode(bytes: &[u8]) -> Result<HdrImage, RendererError> {
+        let image = image::load_from_memory_with_format(bytes, image::ImageFormat::Hdr)
+            .map_err(|e| RendererError::EnvironmentParse(e.to_string()))?
+            .to_rgba32f();
+        Ok(HdrImage {
+            width: image.width(),
+            height: image.height(),
+            rgba: image.into_raw(),
+        })
+    }
+
+    // Neutral studio used until an environment is loaded: sky gradient, grey floor,
+    // a soft key light front left and a weaker rim light behind
+    pub fn studio() -> HdrImage {
+        let (width, height) = (256u32, 128u32);
+        let key = glam::Vec3::new(-0.5, 0.7, 0.6).normalize();
+        let rim = glam::Vec3::new(0.7, 0.3, -0.6).normalize();
+        let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            let theta = std::f32::consts::PI * (y as f32 + 0.5) / height as f32;
+            for x in 0..width {
+                // inverse of equirect_uv in ibl_shader.wgsl
+                let phi = std::f32::consts::TAU * ((x as f32 + 0.5) / width as f32 - 0.5);
+                let dir = glam::Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let sky = glam::Vec3::new(0.55, 0.55, 0.6)
+                    .lerp(glam::Vec3::new(0.8, 0.85, 0.95), dir.y.max(0.0).sqrt());
+                let floor = glam::Vec3::new(0.18, 0.17, 0.16);
+                let mut color = floor.lerp(sky, ((dir.y + 0.05) / 0.1).clamp(0.0, 1.0));
+                let softbox = |center: glam::Vec3, strength: f32| {
+                    let t = ((dir.dot(center) - 0.94) / 0.04).clamp(0.0, 1.0);
+                    t * t * (3.0 - 2.0 * t) * strength
+                };
+                color += glam::Vec3::splat(softbox(key, 10.0) + softbox(rim, 4.0));
+                rgba.extend_from_slice(&[color.x, color.y, color.z, 1.0]);
+            }
+        }
+        HdrImage {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    // Direction towards the brightest part of the image (the sun or the key softbox):
+    // luminance weighted mean over the pixels at least half as bright as the brightest
+    pub fn dominant_direction(&self) -> glam::Vec3 {
+        let luminance = |p: &[f32]| 0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2];
+        let brightest = self
+            .rgba
+            .chunks_exact(4)
+            .map(luminance)
+            .fold(0.0f32, f32::max);
+        let mut sum = glam::Vec3::ZERO;
+        for (i, pixel) in self.rgba.chunks_exact(4).enumerate() {
+            let lum = luminance(pixel);
+            if lum < brightest * 0.5 {
+                continue;
+            }
+            let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+            // same mapping as HdrImage::studio
+            let theta = std::f32::consts::PI * (y as f32 + 0.5) / self.height as f32;
+            let phi = std::f32::consts::TAU * ((x as f32 + 0.5) / self.width as f32 - 0.5);
+            let dir = glam::Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            sum += dir * lum * theta.sin();
+        }
+        sum.try_normalize().unwrap_or(glam::Vec3::Y)
+    }
+
+    // Halves the image with a 2x2 box filter until it is at most `max_width` wide
+    pub fn fit_width(mut self, max_width: u32) -> HdrImage {
+        while self.width > max_width.max(1) && self.height > 1 {
+            let (width, height) = (self.width / 2, self.height / 2);
+            let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    for c in 0..4 {
+                        let at = |dx: u32, dy: u32| {
+                            self.rgba[(((y * 2 + dy) * self.width + x * 2 + dx) * 4 + c) as usize]
+                        };
+                        rgba.push((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) * 0.25);
+                    }
+                }
+            }
+            self = HdrImage {
+                width,
+                height,
+                rgba,
+            };
+        }
+        self
+    }
+}
+
+// Baked IBL maps for one environment, bound as group 3 of the PBR pipeline
+pub struct Environment {
+    // source cubemap with a full mip chain, for backgrounds
+    pub environment_view: wgpu::TextureView,
+    pub irradiance_view: wgpu::TextureView,
+    pub prefiltered_view: wgpu::TextureView,
+    pub uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    // towards the brightest light in the image, casts shadows when no directional light does
+    pub key_direction: glam::Vec3,
+    intensity: std::cell::Cell<f32>,
+}
+
+impl Environment {
+    pub fn intensity(&self) -> f32 {
+        self.intensity.get()
+    }
+
+    // Scales both the diffuse and the specular image based lighting
+    pub fn set_intensity(&self, queue: &wgpu::Queue, intensity: f32) {
+        self.intensity.set(intensity);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::bytes_of(&environment_uniform(intensity)),
+        );
+    }
+}
+
+fn environment_uniform(intensity: f32) -> EnvironmentUniform {
+    EnvironmentUniform {
+        intensity,
+        max_lod: (PREFILTER_MIPS - 1) as f32,
+        _pad: [0.0; 2],
+    }
+}
+
+// Prefilters equirectangular HDRs into irradiance and specular cubemaps on the GPU.
+// Everything is drawn with render passes so it also runs on WebGL2-level devices without compute.
+pub struct Ibl {
+    pub environment_layout: wgpu::BindGroupLayout,
+    equirect_layout: wgpu::BindGroupLayout,
+    cube_layout: wgpu::BindGroupLayout,
+    equirect_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    irradiance_pipeline: wgpu::RenderPipeline,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    bake_sampler: wgpu::Sampler,
+    sampler: wgpu::Sampler,
+    // depends only on the BRDF, baked once and shared by every environment
+    brdf_lut: wgpu::TextureView,
+}
+
+impl Ibl {
+    pub async fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Ibl {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("IBL Bake Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ibl_shader.wgsl").into()),
+        });
+        let params_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<IblBakeParams>() as u64),
+            },
+            count: None,
+        };
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let equirect_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IBL Equirect BGL"),
+            entries: &[
+                params_entry,
+                sampler_entry,
+                texture_entry(2, wgpu::TextureViewDimension::D2),
+            ],
+        });
+        let cube_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IBL Cube BGL"),
+            entries: &[
+                params_entry,
+                sampler_entry,
+                texture_entry(3, wgpu::TextureViewDimension::Cube),
+            ],
+        });
+        let environment_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Environment BGL"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    texture_entry(1, wgpu::TextureViewDimension::Cube),
+                    texture_entry(2, wgpu::TextureViewDimension::Cube),
+                    texture_entry(3, wgpu::TextureViewDimension::D2),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline = |layouts: &[&wgpu::BindGroupLayout], entry_point: &str| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("IBL Bake Pipeline Layout"),
+                bind_group_layouts: layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: IBL_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let equirect_pipeline = pipeline(&[&equirect_layout], "fs_equirect_to_cube");
+        let downsample_pipeline = pipeline(&[&cube_layout], "fs_downsample");
+        let irradiance_pipeline = pipeline(&[&cube_layout], "fs_irradiance");
+        let prefilter_pipeline = pipeline(&[&cube_layout], "fs_prefilter");
+        let brdf_pipeline = pipeline(&[], "fs_brdf_lut");
+
+        let bake_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Bake Sampler"),
+            // wraps around the equirect seam, clamps at the poles
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let brdf_lut = create_ibl_texture(device, "BRDF LUT", BRDF_LUT_SIZE, 1, 1)
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        {
+            let mut pass = begin_bake_pass(&mut encoder, &brdf_lut);
+            pass.set_pipeline(&brdf_pipeline);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+        logger::log("BRDF LUT baked");
+
+        Ibl {
+            environment_layout,
+            equirect_layout,
+            cube_layout,
+            equirect_pipeline,
+            downsample_pipeline,
+            irradiance_pipeline,
+            prefilter_pipeline,
+            bake_sampler,
+            sampler,
+            brdf_lut,
+        }
+    }
+
+    // Uploads an equirectangular image and bakes its IBL maps. Images wider than the
+    // device allows are box-filtered down first.
+    pub fn bake(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: HdrImage,
+    ) -> Result<Environment, RendererError> {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let image = image.fit_width(max_size);
+        let key_direction = image.dominant_direction();
+        RendererError::check_limit(
+            "max_texture_dimension_2d",
+            image.height as u64,
+            max_size as u64,
+        )?;
+        let half_pixels: Vec<u16> = image
+            .rgba
+            .iter()
+            // the sun in an HDR can exceed the f16 range, keep it finite instead of infinite
+            .map(|&v| half::f16::from_f32(v.min(65504.0)).to_bits())
+            .collect();
+        let equirect = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Environment Equirect"),
+                size: wgpu::Extent3d {
+                    width: image.width,
+                    height: image.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: IBL_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&half_pixels),
+        );
+        let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let environment_mips = ENVIRONMENT_SIZE.ilog2() + 1;
+        let environment = create_ibl_texture(
+            device,
+            "Environment Cube",
+            ENVIRONMENT_SIZE,
+            6,
+            environment_mips,
+        );
+        let irradiance = create_ibl_texture(device, "Irradiance Cube", IRRADIANCE_SIZE, 6, 1);
+        let prefiltered = create_ibl_texture(
+            device,
+            "Prefiltered Cube",
+            PREFILTER_SIZE,
+            6,
+            PREFILTER_MIPS,
+        );
+        let cube_view = |texture: &wgpu::Texture, base_mip_level: u32, mip_level_count| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                base_mip_level,
+                mip_level_count,
+                ..Default::default()
+            })
+        };
+        let environment_view = cube_view(&environment, 0, None);
+
+        // (pipeline, bind group, target texture, target mip, params) for every face of every pass
+        let mut passes: Vec<(
+            &wgpu::RenderPipeline,
+            usize,
+            &wgpu::Texture,
+            u32,
+            IblBakeParams,
+        )> = Vec::new();
+        let mut sources: Vec<wgpu::TextureView> = vec![equirect_view];
+        for face in 0..6 {
+            let params = IblBakeParams {
+                face,
+                source_size: ENVIRONMENT_SIZE as f32,
+                ..Default::default()
+            };
+            passes.push((&self.equirect_pipeline, 0, &environment, 0, params));
+        }
+        for mip in 1..environment_mips {
+            sources.push(cube_view(&environment, mip - 1, Some(1)));
+            for face in 0..6 {
+                let params = IblBakeParams {
+                    face,
+                    ..Default::default()
+                };
+                passes.push((
+                    &self.downsample_pipeline,
+                    mip as usize,
+                    &environment,
+                    mip,
+                    params,
+                ));
+            }
+        }
+        let full_environment = sources.len();
+        sources.push(cube_view(&environment, 0, None));
+        for face in 0..6 {
+            let params = IblBakeParams {
+                face,
+                // a mip a few times the irradiance size keeps the convolution smooth
+                source_lod: (ENVIRONMENT_SIZE / (IRRADIANCE_SIZE / 2)).ilog2() as f32,
+                sample_count: IRRADIANCE_STEPS,
+                ..Default::default()
+            };
+            passes.push((
+                &self.irradiance_pipeline,
+                full_environment,
+                &irradiance,
+                0,
+                params,
+            ));
+        }
+        for mip in 0..PREFILTER_MIPS {
+            for face in 0..6 {
+                let params = IblBakeParams {
+                    face,
+                    roughness: mip as f32 / (PREFILTER_MIPS - 1) as f32,
+                    source_lod: (ENVIRONMENT_SIZE / PREFILTER_SIZE).ilog2() as f32,
+                    source_size: ENVIRONMENT_SIZE as f32,
+                    sample_count: PREFILTER_SAMPLES,
+                    ..Default::default()
+                };
+                passes.push((
+                    &self.prefilter_pipeline,
+                    full_environment,
+                    &prefiltered,
+                    mip,
+                    params,
+                ));
+            }
+        }
+
+        // one dynamic-offset slot per pass, same scheme as CameraUniform::aligned_size
+        let align = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let stride = (std::mem::size_of::<IblBakeParams>() as u64).div_ceil(align) * align;
+        let mut params_bytes = vec![0u8; (stride * passes.len() as u64) as usize];
+        for (i, (_, _, _, _, params)) in passes.iter().enumerate() {
+            let start = i * stride as usize;
+            params_bytes[start..start + std::mem::size_of::<IblBakeParams>()]
+                .copy_from_slice(bytemuck::bytes_of(params));
+        }
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("IBL Bake Params"),
+            contents: &params_bytes,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_groups: Vec<wgpu::BindGroup> = sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| {
+                let (layout, binding) = if i == 0 {
+                    (&self.equirect_layout, 2)
+                } else {
+                    (&self.cube_layout, 3)
+                };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("IBL Bake BG"),
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                buffer: &params_buffer,
+                                offset: 0,
+                                size: wgpu::BufferSize::new(
+                                    std::mem::size_of::<IblBakeParams>() as u64
+                                ),
+                            }),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.bake_sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding,
+                            resource: wgpu::BindingResource::TextureView(source),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Bake Encoder"),
+        });
+        for (i, (pipeline, source, target, mip, params)) in passes.iter().enumerate() {
+            let target_view = target.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: *mip,
+                mip_level_count: Some(1),
+                base_array_layer: params.face,
+                array_layer_count: Some(1),
+                ..Default::default()
+            });
+            let mut pass = begin_bake_pass(&mut encoder, &target_view);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_groups[*source], &[(i as u64 * stride) as u32]);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+        logger::log(&format!(
+            "environment baked from {}x{} HDR",
+            image.width, image.height
+        ));
+
+        let irradiance_view = cube_view(&irradiance, 0, None);
+        let prefiltered_view = cube_view(&prefiltered, 0, None);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Uniform"),
+            contents: bytemuck::bytes_of(&environment_uniform(1.0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Environment BG"),
+            layout: &self.environment_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&irradiance_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&prefiltered_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.brdf_lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        Ok(Environment {
+            environment_view,
+            irradiance_view,
+            prefiltered_view,
+            uniform_buffer,
+            bind_group,
+            key_direction,
+            intensity: std::cell::Cell::new(1.0),
+        })
+    }
+}
+
+// Square render target for the bake passes, `layers` is 6 for cubemaps
+fn create_ibl_texture(
+    device: &wgpu::Device,
+    label: &str,
+    size: u32,
+    layers: u32,
+    mip_level_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: IBL_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    })
+}
+
+fn begin_bake_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    target: &'a wgpu::TextureView,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("IBL Bake Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: wgpu::StoreOp::Store,
+            },
+            depth_slice: None,
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
@@ -0,0 +1,190 @@
+// One-off passes that turn an equirectangular HDR into the maps sampled by pbr_fragment_shader.wgsl.
+// Every pass draws a fullscreen triangle into one cubemap face / mip, or into the BRDF LUT.
+
+const PI : f32 = 3.14159265;
+
+// === Per pass parameters (one dynamic-offset slot per face / mip) ===
+struct BakeParams {
+    face         : u32,
+    roughness    : f32,
+    source_lod   : f32,  // mip of the source cubemap to read
+    source_size  : f32,  // width of the source cubemap's base mip (the face width for fs_equirect_to_cube)
+    sample_count : u32,
+    _pad0        : u32,
+    _pad1        : u32,
+    _pad2        : u32,
+};
+@group(0) @binding(0) var<uniform> params : BakeParams;
+@group(0) @binding(1) var source_sampler : sampler;
+@group(0) @binding(2) var equirect : texture_2d<f32>;
+@group(0) @binding(3) var source_cube : texture_cube<f32>;
+
+struct VSOut {
+    @builtin(position) position : vec4<f32>,
+    @location(0) uv : vec2<f32>,   // 0..1, origin top-left like texel coordinates
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index : u32) -> VSOut {
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out : VSOut;
+    out.position = vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    return out;
+}
+
+// World direction through `uv` of cubemap `face` (+X, -X, +Y, -Y, +Z, -Z)
+fn cube_direction(face : u32, uv : vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    var dir : vec3<f32>;
+    switch face {
+        case 0u: { dir = vec3<f32>(1.0, -st.y, -st.x); }
+        case 1u: { dir = vec3<f32>(-1.0, -st.y, st.x); }
+        case 2u: { dir = vec3<f32>(st.x, 1.0, st.y); }
+        case 3u: { dir = vec3<f32>(st.x, -1.0, -st.y); }
+        case 4u: { dir = vec3<f32>(st.x, -st.y, 1.0); }
+        default: { dir = vec3<f32>(-st.x, -st.y, -1.0); }
+    }
+    return normalize(dir);
+}
+
+fn equirect_uv(dir : vec3<f32>) -> vec2<f32> {
+    return vec2<f32>(0.5 + atan2(dir.z, dir.x) / (2.0 * PI), acos(clamp(dir.y, -1.0, 1.0)) / PI);
+}
+
+// --- equirectangular HDR -> environment cubemap (mip 0), 2x2 supersampled ---
+@fragment
+fn fs_equirect_to_cube(in : VSOut) -> @location(0) vec4<f32> {
+    let texel = 0.25 / params.source_size;
+    var color = vec3<f32>(0.0);
+    for (var i = 0u; i < 4u; i++) {
+        let offset = vec2<f32>(f32(i & 1u), f32(i >> 1u)) * 2.0 - 1.0;
+        let dir = cube_direction(params.face, in.uv + offset * texel);
+        color += textureSampleLevel(equirect, source_sampler, equirect_uv(dir), 0.0).rgb;
+    }
+    return vec4<f32>(color * 0.25, 1.0);
+}
+
+// --- next environment mip from a view of the previous one (bilinear = 2x2 box) ---
+@fragment
+fn fs_downsample(in : VSOut) -> @location(0) vec4<f32> {
+    let dir = cube_direction(params.face, in.uv);
+    return vec4<f32>(textureSampleLevel(source_cube, source_sampler, dir, params.source_lod).rgb, 1.0);
+}
+
+fn tangent_frame(n : vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return mat3x3<f32>(tangent, bitangent, n);
+}
+
+// --- cosine-weighted hemisphere convolution for the diffuse term ---
+@fragment
+fn fs_irradiance(in : VSOut) -> @location(0) vec4<f32> {
+    let frame = tangent_frame(cube_direction(params.face, in.uv));
+    let steps = params.sample_count;
+    var irradiance = vec3<f32>(0.0);
+    for (var i = 0u; i < steps; i++) {
+        let phi = 2.0 * PI * (f32(i) + 0.5) / f32(steps);
+        for (var j = 0u; j < steps / 4u; j++) {
+            let theta = 0.5 * PI * (f32(j) + 0.5) / f32(steps / 4u);
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let sample = textureSampleLevel(source_cube, source_sampler, frame * local, params.source_lod).rgb;
+            irradiance += sample * cos(theta) * sin(theta);
+        }
+    }
+    return vec4<f32>(PI * irradiance / f32(steps * (steps / 4u)), 1.0);
+}
+
+fn radical_inverse(bits_in : u32) -> f32 {
+    var bits = bits_in;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i : u32, count : u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), radical_inverse(i));
+}
+
+// GGX half vector around +Z for perceptual `roughness`
+fn importance_sample_ggx(xi : vec2<f32>, roughness : f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+fn distribution_ggx(n_dot_h : f32, roughness : f32) -> f32 {
+    let a2 = roughness * roughness * roughness * roughness;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+// --- specular prefilter for one roughness (mip), filtered importance sampling ---
+@fragment
+fn fs_prefilter(in : VSOut) -> @location(0) vec4<f32> {
+    let n = cube_direction(params.face, in.uv);
+    if (params.roughness <= 0.0) {
+        return vec4<f32>(textureSampleLevel(source_cube, source_sampler, n, params.source_lod).rgb, 1.0);
+    }
+    let frame = tangent_frame(n);
+    // solid angle of one texel of the source's base mip
+    let texel_solid_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+    var color = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        let h = frame * importance_sample_ggx(hammersley(i, params.sample_count), params.roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(dot(n, h), 0.0);
+            // n == v, so pdf = D * n_dot_h / (4 * v_dot_h) reduces to D / 4
+            let pdf = distribution_ggx(n_dot_h, params.roughness) / 4.0 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf + 0.0001);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            color += textureSampleLevel(source_cube, source_sampler, l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(color / max(weight, 0.0001), 1.0);
+}
+
+fn geometry_schlick_ggx_ibl(n_dot_x : f32, roughness : f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+// --- split-sum BRDF LUT: x = N.V, y = roughness -> (scale, bias) applied to F0 ---
+@fragment
+fn fs_brdf_lut(in : VSOut) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.uv.x, 0.001);
+    let roughness = in.uv.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let count = 256u;
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < count; i++) {
+        let h = importance_sample_ggx(hammersley(i, count), roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        if (n_dot_l > 0.0) {
+            let g = geometry_schlick_ggx_ibl(n_dot_v, roughness) * geometry_schlick_ggx_ibl(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    return vec4<f32>(scale / f32(count), bias / f32(count), 0.0, 1.0);
+}
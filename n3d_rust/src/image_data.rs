@@ -0,0 +1,160 @@
+use crate::background::{linear_to_srgb, srgb_to_linear};
+use crate::renderer_error::RendererError;
+use gltf::image::{Format, Source};
+
+// Decodes a glTF image. Only PNG and JPEG can be decoded; other encodings (KTX2, WebP, ..)
+// are reported with their MIME type.
+pub fn decode_gltf_image(
+    image: &gltf::Image,
+    buffers: &[gltf::buffer::Data],
+) -> Result<gltf::image::Data, RendererError> {
+    gltf::image::Data::from_source(image.source(), None, buffers).map_err(|e| match e {
+        gltf::Error::UnsupportedImageEncoding => {
+            let mime_type = match image.source() {
+                Source::View { mime_type, .. } => Some(mime_type),
+                Source::Uri { mime_type, .. } => mime_type,
+            };
+            RendererError::UnsupportedImageFormat {
+                image: image.index(),
+                format: mime_type.unwrap_or("unknown").to_string(),
+            }
+        }
+        e => RendererError::GltfParse(format!("image {}: {}", image.index(), e)),
+    })
+}
+
+// Pixels of a decoded glTF image widened to RGBA. Grey images replicate into RGB, missing
+// alpha is opaque.
+pub enum ImagePixels {
+    Rgba8(Vec<u8>),
+    Rgba16(Vec<u16>),
+    // linear float data (Radiance / OpenEXR sources)
+    Rgba32F(Vec<f32>),
+}
+
+impl ImagePixels {
+    pub fn from_gltf(image: &gltf::image::Data) -> ImagePixels {
+        let pixels = &image.pixels;
+        // the image crate hands over 16 and 32 bit channels in native byte order
+        let u16s = || -> Vec<u16> {
+            pixels
+                .chunks_exact(2)
+                .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                .collect()
+        };
+        let f32s = || -> Vec<f32> {
+            pixels
+                .chunks_exact(4)
+                .map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                .collect()
+        };
+        match image.format {
+            Format::R8 => ImagePixels::Rgba8(widen(pixels, 1, u8::MAX)),
+            Format::R8G8 => ImagePixels::Rgba8(widen(pixels, 2, u8::MAX)),
+            Format::R8G8B8 => ImagePixels::Rgba8(widen(pixels, 3, u8::MAX)),
+            Format::R8G8B8A8 => ImagePixels::Rgba8(pixels.clone()),
+            Format::R16 => ImagePixels::Rgba16(widen(&u16s(), 1, u16::MAX)),
+            Format::R16G16 => ImagePixels::Rgba16(widen(&u16s(), 2, u16::MAX)),
+            Format::R16G16B16 => ImagePixels::Rgba16(widen(&u16s(), 3, u16::MAX)),
+            Format::R16G16B16A16 => ImagePixels::Rgba16(u16s()),
+            Format::R32G32B32FLOAT => ImagePixels::Rgba32F(widen(&f32s(), 3, 1.0)),
+            Format::R32G32B32A32FLOAT => ImagePixels::Rgba32F(f32s()),
+        }
+    }
+
+    // Texture format and tightly packed texels for sampling the image as color (`srgb`) or
+    // data. With `half_float` (Rgba16Float renderable and filterable) 16-bit and float images
+    // keep their precision, otherwise they are quantized to 8 bits.
+    pub fn texture_data(&self, srgb: bool, half_float: bool) -> (wgpu::TextureFormat, Vec<u8>) {
+        let rgba8 = if srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        match self {
+            ImagePixels::Rgba8(texels) => (rgba8, texels.clone()),
+            ImagePixels::Rgba16(texels) if half_float => {
+                // there is no sRGB float format, decode color on the way in
+                let linear = texels.iter().enumerate().map(|(i, &v)| {
+                    let v = v as f32 / u16::MAX as f32;
+                    if srgb && i % 4 != 3 {
+                        srgb_to_linear(v)
+                    } else {
+                        v
+                    }
+                });
+                (wgpu::TextureFormat::Rgba16Float, to_half_bytes(linear))
+            }
+            ImagePixels::Rgba16(texels) => {
+                (rgba8, texels.iter().map(|&v| (v >> 8) as u8).collect())
+            }
+            ImagePixels::Rgba32F(texels) if half_float => (
+                wgpu::TextureFormat::Rgba16Float,
+                to_half_bytes(texels.iter().copied()),
+            ),
+            ImagePixels::Rgba32F(texels) => {
+                let texels = texels
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &v)| {
+                        let v = v.clamp(0.0, 1.0);
+                        let v = if srgb && i % 4 != 3 {
+                            linear_to_srgb(v)
+                        } else {
+                            v
+                        };
+                        (v * 255.0 + 0.5) as u8
+                    })
+                    .collect();
+                (rgba8, texels)
+            }
+        }
+    }
+}
+
+// Pads each `channels`-wide pixel out to RGBA
+fn widen<T: Copy>(pixels: &[T], channels: usize, opaque: T) -> Vec<T> {
+    let mut rgba = Vec::with_capacity(pixels.len() / channels * 4);
+    for pixel in pixels.chunks_exact(channels) {
+        match *pixel {
+            [grey] => rgba.extend_from_slice(&[grey, grey, grey, opaque]),
+            [grey, alpha] => rgba.extend_from_slice(&[grey, grey, grey, alpha]),
+            [r, g, b] => rgba.extend_from_slice(&[r, g, b, opaque]),
+            _ => rgba.extend_from_slice(pixel),
+        }
+    }
+    rgba
+}
+
+// Float texels beyond the f16 range are clamped to its largest finite value, not infinity
+fn to_half_bytes(values: impl Iterator<Item = f32>) -> Vec<u8> {
+    values
+        .flat_map(|v| half::f16::from_f32(v.clamp(-65504.0, 65504.0)).to_ne_bytes())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one image of undecodable bytes in a buffer view
+    const WEBP_IMAGE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "byteLength": 3, "uri": "data:application/octet-stream;base64,AAAA" }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 3 }],
+        "images": [{ "bufferView": 0, "mimeType": "image/webp" }]
+    }"#;
+
+    #[test]
+    fn undecodable_image_reports_its_format() {
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(WEBP_IMAGE.as_bytes()).unwrap();
+        let buffers = gltf::import_buffers(&document, None, blob).unwrap();
+        let image = document.images().next().unwrap();
+        match decode_gltf_image(&image, &buffers) {
+            Err(RendererError::UnsupportedImageFormat { image, format }) => {
+                assert_eq!((image, format.as_str()), (0, "image/webp"))
+            }
+            other => panic!("expected UnsupportedImageFormat, got {:?}", other.err()),
+        }
+    }
+}
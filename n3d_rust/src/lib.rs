@@ -1,143 +1,393 @@
 #![allow(warnings)]
+mod animation;
+pub mod background;
 mod camera_math;
 mod config_pipeline;
 mod custom_structs;
+mod ibl;
+mod image_data;
+pub mod lights;
+mod logger;
+mod mipmaps;
 mod model_exec;
+mod morph_targets;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod offscreen;
 mod render_loop;
+pub mod renderer_error;
+pub mod renderer_options;
 mod saved_state;
+mod scene_graph;
+pub mod shadows;
+mod skinning;
+mod tangents;
+pub mod tone_mapping;
+#[cfg(test)]
+mod uniform_layouts;
+mod viewer;
 use glam::Vec3;
 use gltf::Gltf;
 mod model_exec2;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::future_to_promise;
 use wasm_bindgen_futures::spawn_local;
-use web_sys::console;
 
 #[wasm_bindgen]
 #[cfg(target_arch = "wasm32")]
 struct Renderer {
-    saved_gpu: saved_state::SavedState,
-    model_manager: model_exec::ModelExec,
-    pipeline: config_pipeline::ConfigPipeline,
-    render_manager: render_loop::RenderLoop,
-    camera: camera_math::Camera,
+    state: Rc<RendererState>,
 }
+
+// Everything a frame needs, shared between the Renderer and the requestAnimationFrame closure
+#[cfg(target_arch = "wasm32")]
+struct RendererState {
+    viewer: viewer::Viewer,
+    frame_loop: RefCell<Option<Closure<dyn FnMut(f64)>>>,
+    frame_request: Cell<Option<i32>>,
+    // requestAnimationFrame timestamp of the last looped frame, in milliseconds
+    last_frame_time: Cell<Option<f64>>,
+    resize_observer: RefCell<Option<(web_sys::ResizeObserver, Closure<dyn FnMut()>)>>,
+}
+
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 impl Renderer {
+    // `canvas` is a <canvas> element or its id, `options` is read by RendererOptions::from_js
     #[wasm_bindgen(constructor)]
-    pub async fn new(height: u32, width: u32) -> js_sys::Promise {
+    pub async fn new(canvas: JsValue, options: JsValue) -> js_sys::Promise {
         // Convert the Rust Future into a JS Promise
         future_to_promise(async move {
-            let saved_gpu = saved_state::SavedState::new().await;
-            let model_manager = model_exec::ModelExec::new().await;
-            let pipeline = config_pipeline::ConfigPipeline::new().await;
-            let render_manager = render_loop::RenderLoop::new().await;
-            let camera = camera_math::Camera::new(
-                Vec3::new(0.0, 0.0, 5.0),     // eye
-                Vec3::ZERO,                   // target
-                width as f32 / height as f32, // aspect
-            )
-            .await;
-            // Wrap Manager in JsValue
-            let render = Renderer {
-                saved_gpu,
-                model_manager,
-                pipeline,
-                render_manager,
-                camera,
-            };
+            let options = renderer_options::RendererOptions::from_js(&options);
+            let canvas = saved_state::SavedState::resolve_canvas(&canvas)?;
+            let saved_gpu = saved_state::SavedState::new(canvas, &options).await?;
+            let render = Renderer::from_saved_gpu(saved_gpu, &options).await?;
             // Return Ok(JsValue) as expected by future_to_promise
             Ok(JsValue::from(render))
         })
     }
-    pub async fn render(&self, bytes: &[u8]) -> JsValue {
-        use gltf::texture;
+    // Another Renderer drawing into `canvas` with this one's adapter, device and queue
+    pub async fn share_device(
+        &self,
+        canvas: JsValue,
+        options: JsValue,
+    ) -> Result<Renderer, renderer_error::RendererError> {
+        let options = renderer_options::RendererOptions::from_js(&options);
+        let canvas = saved_state::SavedState::resolve_canvas(&canvas)?;
+        let saved_gpu = self.state.viewer.saved_gpu.share(canvas).await?;
+        Renderer::from_saved_gpu(saved_gpu, &options).await
+    }
+    // Uploads the model and keeps its GPU resources for draw_frame.
+    // `scene` picks a glTF scene by index, otherwise the default scene is drawn.
+    // Rejects with a RendererError object (`kind`, `message`) if the asset can't be used
+    // or has no such scene
+    pub async fn load_model(
+        &self,
+        bytes: &[u8],
+        scene: Option<f64>,
+    ) -> Result<(), renderer_error::RendererError> {
+        let scene = scene.map(js_index).transpose()?;
+        self.state.viewer.load_model(bytes, scene).await
+    }
+    // Lights the model with an equirectangular Radiance .hdr instead of the built-in studio
+    pub fn load_environment(&self, hdr_bytes: &[u8]) -> Result<(), renderer_error::RendererError> {
+        self.state.viewer.load_environment(hdr_bytes)
+    }
+    pub fn set_environment_intensity(&self, intensity: f32) {
+        self.state.viewer.set_environment_intensity(intensity);
+    }
+    // "neutral" (default, Khronos PBR Neutral), "aces", "agx", "reinhard" or "none".
+    // Throws a RendererError for other names
+    pub fn set_tone_mapping(&self, name: &str) -> Result<(), renderer_error::RendererError> {
+        let mapping = tone_mapping::ToneMapping::from_name(name).ok_or_else(|| {
+            renderer_error::RendererError::InvalidArgument(format!(
+                "unknown tone mapping {:?}",
+                name
+            ))
+        })?;
+        self.state.viewer.set_tone_mapping(mapping);
+        Ok(())
+    }
+    // Linear multiplier on the scene before tone mapping, 1 = as lit
+    pub fn set_exposure(&self, exposure: f32) {
+        self.state.viewer.set_exposure(exposure);
+    }
+    // `background` is read by BackgroundMode::from_js, e.g. { mode: "color", color: "#ffffff" }.
+    // Throws a RendererError for unknown modes or when the canvas can't be transparent
+    pub fn set_background(&self, background: JsValue) -> Result<(), renderer_error::RendererError> {
+        let mode = background::BackgroundMode::from_js(&background)?;
+        self.state.viewer.set_background(mode)
+    }
+    // PNG or JPEG drawn behind the model, scaled to cover the canvas
+    pub fn load_background_image(&self, bytes: &[u8]) -> Result<(), renderer_error::RendererError> {
+        self.state.viewer.load_background_image(bytes)
+    }
+    // Adds a world space light read by Light::from_js, e.g. { type: "point", position: [0, 2, 2],
+    // intensity: 20 }; returns the number of added lights. Throws a RendererError if malformed
+    pub fn add_light(&self, light: JsValue) -> Result<u32, renderer_error::RendererError> {
+        let light = lights::Light::from_js(&light)?;
+        Ok(self.state.viewer.add_light(light) as u32)
+    }
+    // `settings` is read by ShadowSettings::from_js, e.g. { softness: 3, ground: true }.
+    // Throws a RendererError if it is not an object
+    pub fn set_shadows(&self, settings: JsValue) -> Result<(), renderer_error::RendererError> {
+        let settings = shadows::ShadowSettings::from_js(&settings)?;
+        self.state.viewer.set_shadows(settings);
+        Ok(())
+    }
+    // Removes the lights added with add_light, the model's own lights stay
+    pub fn clear_lights(&self) {
+        self.state.viewer.clear_lights();
+    }
+    // Draws a single frame with the current camera; no-op until a model is loaded
+    pub fn draw_frame(&self) {
+        self.state.viewer.draw_frame();
+    }
+    // Starts redrawing every animation frame until stop() is called; playing animations
+    // advance with the frame timestamps
+    pub fn start(&self) {
+        if self.state.frame_loop.borrow().is_some() {
+            return;
+        }
+        let state = self.state.clone();
+        let frame_loop = Closure::<dyn FnMut(f64)>::new(move |timestamp: f64| {
+            // hidden tabs get no frames, don't jump ahead by the time away
+            let delta = state
+                .last_frame_time
+                .replace(Some(timestamp))
+                .map_or(0.0, |last| ((timestamp - last) / 1000.0).clamp(0.0, 0.25));
+            state.viewer.advance_animation(delta as f32);
+            state.viewer.draw_frame();
+            state.request_frame();
+        });
+        *self.state.frame_loop.borrow_mut() = Some(frame_loop);
+        self.state.request_frame();
+    }
+    pub fn stop(&self) {
+        if let Some(handle) = self.state.frame_request.take() {
+            if let Some(window) = web_sys::window() {
+                let _ = window.cancel_animation_frame(handle);
+            }
+        }
+        // dropping the closure also releases its handle on the shared state
+        self.state.frame_loop.borrow_mut().take();
+        self.state.last_frame_time.set(None);
+    }
+    pub fn is_running(&self) -> bool {
+        self.state.frame_loop.borrow().is_some()
+    }
+    // Matches the surface to the canvas' CSS size times devicePixelRatio
+    pub fn reconfigure_surface(&self) {
+        self.state.fit_to_canvas();
+    }
+    // Sets an explicit surface size in device pixels
+    pub fn resize(&self, width: u32, height: u32) {
+        self.state.viewer.resize(width, height);
+    }
+    // Calls reconfigure_surface whenever the canvas' layout size changes
+    pub fn observe_resize(&self) {
+        if self.state.resize_observer.borrow().is_some() {
+            return;
+        }
+        let state = self.state.clone();
+        let on_resize = Closure::<dyn FnMut()>::new(move || {
+            state.fit_to_canvas();
+            // resizing clears the canvas, so repaint now instead of on the next frame
+            state.viewer.draw_frame();
+        });
+        match web_sys::ResizeObserver::new(on_resize.as_ref().unchecked_ref()) {
+            Ok(observer) => {
+                observer.observe(self.state.viewer.saved_gpu.get_canvas());
+                *self.state.resize_observer.borrow_mut() = Some((observer, on_resize));
+            }
+            Err(e) => logger::log(&format!("ResizeObserver unavailable: {:?}", e)),
+        }
+    }
+    pub fn unobserve_resize(&self) {
+        if let Some((observer, _on_resize)) = self.state.resize_observer.borrow_mut().take() {
+            observer.disconnect();
+        }
+    }
+    // [{ name, duration }] for the model's glTF animations, name is null when unnamed and
+    // duration in seconds
+    pub fn animations(&self) -> js_sys::Array {
+        self.state
+            .viewer
+            .animations()
+            .into_iter()
+            .map(|(name, duration)| {
+                let clip = js_sys::Object::new();
+                let _ = js_sys::Reflect::set(&clip, &"name".into(), &name.into());
+                let _ = js_sys::Reflect::set(&clip, &"duration".into(), &duration.into());
+                JsValue::from(clip)
+            })
+            .collect()
+    }
+    // Plays a clip, given by index or name, from its start. With `fade` seconds the clip that
+    // was playing blends into it. Throws a RendererError for unknown clips
+    pub fn play_animation(
+        &self,
+        clip: JsValue,
+        fade: Option<f32>,
+    ) -> Result<(), renderer_error::RendererError> {
+        let index = match (clip.as_f64(), clip.as_string()) {
+            (Some(index), _) => Some(js_index(index)?),
+            (None, Some(name)) => self.state.viewer.animation_index(&name),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            renderer_error::RendererError::InvalidArgument(format!("unknown animation {:?}", clip))
+        })?;
+        self.state.viewer.play_animation(index, fade.unwrap_or(0.0))
+    }
+    pub fn pause_animation(&self) {
+        self.state.viewer.pause_animation();
+    }
+    pub fn resume_animation(&self) {
+        self.state.viewer.resume_animation();
+    }
+    pub fn is_animation_playing(&self) -> bool {
+        self.state.viewer.is_animation_playing()
+    }
+    // Loop (default) or stop at the clip's end
+    pub fn set_animation_loop(&self, looping: bool) {
+        self.state.viewer.set_animation_loop(looping);
+    }
+    // 1 = as authored, negative plays backwards
+    pub fn set_animation_speed(&self, speed: f32) {
+        self.state.viewer.set_animation_speed(speed);
+    }
+    // Jumps to `time` seconds into the current clip
+    pub fn seek_animation(&self, time: f32) {
+        self.state.viewer.seek_animation(time);
+    }
+    pub fn animation_time(&self) -> f32 {
+        self.state.viewer.animation_time()
+    }
+    // For pages that call draw_frame themselves instead of start(): moves playback on by
+    // `delta` seconds
+    pub fn advance_animation(&self, delta: f32) {
+        self.state.viewer.advance_animation(delta);
+    }
+    // [{ name, morphTargets }] for the model's glTF meshes, name is null when unnamed
+    pub fn meshes(&self) -> js_sys::Array {
+        self.state
+            .viewer
+            .meshes()
+            .into_iter()
+            .map(|(name, morph_targets)| {
+                let mesh = js_sys::Object::new();
+                let _ = js_sys::Reflect::set(&mesh, &"name".into(), &name.into());
+                let _ = js_sys::Reflect::set(
+                    &mesh,
+                    &"morphTargets".into(),
+                    &(morph_targets as u32).into(),
+                );
+                JsValue::from(mesh)
+            })
+            .collect()
+    }
+    // Morph target weights for every instance of a mesh, given by index or name; missing
+    // weights are 0. Throws a RendererError for unknown meshes
+    pub fn set_morph_weights(
+        &self,
+        mesh: JsValue,
+        weights: Vec<f32>,
+    ) -> Result<(), renderer_error::RendererError> {
+        let index = match (mesh.as_f64(), mesh.as_string()) {
+            (Some(index), _) => Some(js_index(index)?),
+            (None, Some(name)) => self.state.viewer.mesh_index(&name),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            renderer_error::RendererError::InvalidArgument(format!("unknown mesh {:?}", mesh))
+        })?;
+        self.state.viewer.set_morph_weights(index, &weights)
+    }
+    // Orbit the camera around its target, angles in radians
+    pub fn rotate(&self, delta_yaw: f32, delta_pitch: f32) {
+        self.state
+            .viewer
+            .camera
+            .borrow_mut()
+            .orbit(delta_yaw, delta_pitch);
+    }
+    // Dolly in (negative) or out (positive); the orbit distance is scaled by exp(delta)
+    pub fn zoom(&self, delta: f32) {
+        self.state.viewer.camera.borrow_mut().dolly(delta);
+    }
+    // Move camera and target across the view plane, deltas in NDC units
+    pub fn pan(&self, delta_x: f32, delta_y: f32) {
+        self.state.viewer.camera.borrow_mut().pan(delta_x, delta_y);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Renderer {
+    async fn from_saved_gpu(
+        saved_gpu: saved_state::SavedState,
+        options: &renderer_options::RendererOptions,
+    ) -> Result<Renderer, renderer_error::RendererError> {
+        let render = Renderer {
+            state: Rc::new(RendererState {
+                viewer: viewer::Viewer::new(saved_gpu, options).await?,
+                frame_loop: RefCell::new(None),
+                frame_request: Cell::new(None),
+                last_frame_time: Cell::new(None),
+                resize_observer: RefCell::new(None),
+            }),
+        };
+        if options.auto_resize {
+            render.reconfigure_surface();
+            render.observe_resize();
+        }
+        Ok(render)
+    }
+}
 
-        use crate::custom_structs::CustomStructs::MaterialPBR;
+#[cfg(target_arch = "wasm32")]
+impl RendererState {
+    fn fit_to_canvas(&self) {
+        let canvas = self.viewer.saved_gpu.get_canvas();
+        let pixel_ratio = web_sys::window()
+            .map(|window| window.device_pixel_ratio())
+            .unwrap_or(1.0);
+        let (css_width, css_height) = (canvas.client_width(), canvas.client_height());
+        // not laid out yet (e.g. display: none)
+        if css_width <= 0 || css_height <= 0 {
+            return;
+        }
+        self.viewer.resize(
+            (css_width as f64 * pixel_ratio).round() as u32,
+            (css_height as f64 * pixel_ratio).round() as u32,
+        );
+    }
+
+    fn request_frame(&self) {
+        let frame_loop = self.frame_loop.borrow();
+        let (Some(frame_loop), Some(window)) = (frame_loop.as_ref(), web_sys::window()) else {
+            return;
+        };
+        match window.request_animation_frame(frame_loop.as_ref().unchecked_ref()) {
+            Ok(handle) => self.frame_request.set(Some(handle)),
+            Err(e) => logger::log(&format!("requestAnimationFrame failed: {:?}", e)),
+        }
+    }
+}
 
-        let model = self.model_manager.parse_gltf_n_glb(bytes).await;
-        self.model_manager.print_gltf(&model);
-        let (vertShader, fragShader, pbrFragShader) = self.saved_gpu.create_shader_module().await;
-        console::log_1(&"shaders created".into());
-        let (isPbr, model, material, material_pbr, texture) = self
-            .model_manager
-            .build_model(
-                bytes,
-                self.saved_gpu.get_device(),
-                self.saved_gpu.get_queue(),
-            )
-            .await;
-        let (camera_bgl, material_bgl, light_bgl) = self
-            .model_manager
-            .bindGroupLayoutPBR_all(&self.saved_gpu.get_device())
-            .await;
-        let (camera_bg, material_bg, light_bg) = self
-            .model_manager
-            .bindGroupPBR_all(
-                &self.saved_gpu.get_device(),
-                material_pbr,
-                &texture,
-                camera_bgl.clone(),
-                material_bgl.clone(),
-                light_bgl.clone(),
-            )
-            .await;
-        let model_pipeline = self
-            .model_manager
-            .buildPipelinePBR(
-                &self.saved_gpu.get_device(),
-                self.saved_gpu.get_surface_format(),
-                &camera_bgl,
-                &material_bgl,
-                &light_bgl,
-                &vertShader,
-                &pbrFragShader,
-            )
-            .await;
-        let depth_texture = self
-            .model_manager
-            .create_depth_texture(
-                &self.saved_gpu.get_device(),
-                self.saved_gpu
-                    .get_surface()
-                    .get_current_texture()
-                    .unwrap()
-                    .texture
-                    .size()
-                    .width,
-                self.saved_gpu
-                    .get_surface()
-                    .get_current_texture()
-                    .unwrap()
-                    .texture
-                    .size()
-                    .height,
-            )
-            .await;
-        self.render_manager
-            .render2(
-                self.saved_gpu.get_device(),
-                self.saved_gpu.get_queue(),
-                model_pipeline,
-                model,
-                camera_bg,
-                material_bg,
-                light_bg,
-                self.saved_gpu.get_surface(),
-                &depth_texture,
-            )
-            .await;
-        console::log_1(&"render function exited successfully".into());
-        JsValue::NULL
-    }
-    pub async fn reconfigure_surface(&self) -> JsValue {
-        console::log_1(&format!("reconfigure_surface caled: {:?}", 0).into());
-        JsValue::NULL
-    }
-    pub async fn rotate() {}
-    pub async fn zoom() -> JsValue {
-        JsValue::NULL
+// Array index passed as a JS number; -1, NaN or 1.5 must not turn into some other index
+#[cfg(target_arch = "wasm32")]
+fn js_index(value: f64) -> Result<usize, renderer_error::RendererError> {
+    if value.is_finite() && value >= 0.0 && value.fract() == 0.0 {
+        Ok(value as usize)
+    } else {
+        Err(renderer_error::RendererError::InvalidArgument(format!(
+            "index must be a whole number >= 0, got {}",
+            value
+        )))
     }
 }
@@ -0,0 +1,172 @@
+use crate::custom_structs::CustomStructs::{LightsUniform, PunctualLightUniform};
+#[cfg(target_arch = "wasm32")]
+use crate::renderer_error::RendererError;
+use glam::{Mat4, Vec3};
+
+// Size of the light array in the PBR shader; WebGL2-level devices have no storage
+// buffers, so lights live in a fixed size uniform array
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    // cone angles in radians from the spot direction, full intensity inside `inner`
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+// A KHR_lights_punctual light. Intensity is in candela for point and spot lights and
+// in lux for directional ones, color is linear RGB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // distance at which point and spot lights fade out, None = inverse square only
+    pub range: Option<f32>,
+    pub position: Vec3,
+    // where the light shines, -Z of the node for glTF lights
+    pub direction: Vec3,
+}
+
+impl Light {
+    // In node space: at the origin and pointing down -Z
+    pub fn from_gltf(light: &gltf::khr_lights_punctual::Light) -> Light {
+        let kind = match light.kind() {
+            gltf::khr_lights_punctual::Kind::Directional => LightKind::Directional,
+            gltf::khr_lights_punctual::Kind::Point => LightKind::Point,
+            gltf::khr_lights_punctual::Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        };
+        Light {
+            kind,
+            color: light.color(),
+            intensity: light.intensity(),
+            range: light.range(),
+            position: Vec3::ZERO,
+            direction: Vec3::NEG_Z,
+        }
+    }
+
+    // The light placed by a node's world matrix (scale only moves it, range stays as authored)
+    pub fn transformed(&self, world: Mat4) -> Light {
+        Light {
+            position: world.transform_point3(self.position),
+            direction: world
+                .transform_vector3(self.direction)
+                .try_normalize()
+                .unwrap_or(self.direction),
+            ..*self
+        }
+    }
+
+    pub fn to_uniform(&self) -> PunctualLightUniform {
+        let (kind, angle_scale, angle_offset) = match self.kind {
+            LightKind::Directional => (0, 0.0, 0.0),
+            LightKind::Point => (1, 0.0, 0.0),
+            LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                let cos_outer = outer_cone_angle.cos();
+                let scale = 1.0 / (inner_cone_angle.cos() - cos_outer).max(0.001);
+                (2, scale, -cos_outer * scale)
+            }
+        };
+        PunctualLightUniform {
+            position: self.position.to_array(),
+            range: self.range.unwrap_or(0.0),
+            direction: self.direction.normalize_or(Vec3::NEG_Z).to_array(),
+            kind,
+            color: self.color,
+            intensity: self.intensity,
+            angle_scale,
+            angle_offset,
+            _pad: [0.0; 2],
+        }
+    }
+}
+
+// Packs the first MAX_LIGHTS lights for the shader
+pub fn lights_uniform(lights: impl IntoIterator<Item = Light>) -> LightsUniform {
+    let mut uniform = LightsUniform {
+        lights: [PunctualLightUniform::default(); MAX_LIGHTS],
+        count: 0,
+        _pad: [0; 3],
+    };
+    for (slot, light) in uniform.lights.iter_mut().zip(lights) {
+        *slot = light.to_uniform();
+        uniform.count += 1;
+    }
+    uniform
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Light {
+    // { type: "directional" | "point" | "spot", color, intensity, range, position: [x, y, z],
+    //   direction: [x, y, z], innerConeAngle, outerConeAngle }. Missing fields take the glTF
+    // defaults (white, intensity 1, at the origin pointing down -Z, 0 and PI / 4 cone angles);
+    // colors are "#rrggbb" or sRGB [r, g, b] in 0..1 like set_background's.
+    pub fn from_js(value: &wasm_bindgen::JsValue) -> Result<Light, RendererError> {
+        let get = |key: &str| js_sys::Reflect::get(value, &key.into()).unwrap_or_default();
+        let number = |key: &str, default: f32| get(key).as_f64().map_or(default, |n| n as f32);
+        let vector = |key: &str, default: Vec3| {
+            let field = get(key);
+            if field.is_undefined() {
+                return Ok(default);
+            }
+            let components: Vec<f32> = if js_sys::Array::is_array(&field) {
+                js_sys::Array::from(&field)
+                    .iter()
+                    .filter_map(|c| c.as_f64())
+                    .map(|c| c as f32)
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            <[f32; 3]>::try_from(components)
+                .map(Vec3::from_array)
+                .map_err(|_| {
+                    RendererError::InvalidArgument(format!("light {} must be [x, y, z]", key))
+                })
+        };
+        let kind = match get("type").as_string().as_deref() {
+            Some("directional") => LightKind::Directional,
+            Some("point") => LightKind::Point,
+            Some("spot") => LightKind::Spot {
+                inner_cone_angle: number("innerConeAngle", 0.0),
+                outer_cone_angle: number("outerConeAngle", std::f32::consts::FRAC_PI_4),
+            },
+            other => {
+                return Err(RendererError::InvalidArgument(format!(
+                    "unknown light type {:?}",
+                    other.unwrap_or("undefined")
+                )))
+            }
+        };
+        let color = match get("color") {
+            color if color.is_undefined() => [1.0; 3],
+            color => crate::background::color_from_js(&color).ok_or_else(|| {
+                RendererError::InvalidArgument(
+                    "light color must be \"#rrggbb\" or [r, g, b]".to_string(),
+                )
+            })?,
+        };
+        Ok(Light {
+            kind,
+            color,
+            intensity: number("intensity", 1.0),
+            range: get("range").as_f64().map(|range| range as f32),
+            position: vector("position", Vec3::ZERO)?,
+            direction: vector("direction", Vec3::NEG_Z)?,
+        })
+    }
+}
@@ -0,0 +1,16 @@
+// console.log in the browser (web_sys::console panics off wasm). Native builds stay quiet
+// unless N3D_LOG is set, and then write to stderr so stdout is left to the caller.
+pub fn log(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::log_1(&message.into());
+    #[cfg(not(target_arch = "wasm32"))]
+    if enabled() {
+        eprintln!("{}", message);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn enabled() -> bool {
+    static ENABLED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *ENABLED.get_or_init(|| std::env::var_os("N3D_LOG").is_some())
+}
@@ -0,0 +1,24 @@
+// One mip level from the level above it (see mipmaps.rs). The target is half the size,
+// so the bilinear tap at each texel centre averages the 2x2 source texels under it.
+
+@group(0) @binding(0) var source : texture_2d<f32>;
+@group(0) @binding(1) var sourceSampler : sampler;
+
+struct VSOut {
+    @builtin(position) position : vec4<f32>,
+    @location(0) uv : vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> VSOut {
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out : VSOut;
+    out.position = vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(corner.x, 1.0 - corner.y);
+    return out;
+}
+
+@fragment
+fn fs_main(in : VSOut) -> @location(0) vec4<f32> {
+    return textureSampleLevel(source, sourceSampler, in.uv, 0.0);
+}
@@ -1,126 +1,212 @@
+use super::animation::Clip;
 use super::custom_structs::CustomStructs;
+use super::image_data::{self, ImagePixels};
+use super::lights::{lights_uniform, Light, MAX_LIGHTS};
+use super::mipmaps::{self, MipGenerator};
+use super::morph_targets::{self, MorphDeltas, TargetDeltas};
+use super::renderer_error::RendererError;
+use super::scene_graph::SceneGraph;
+use super::shadows::{self, Shadows};
+use super::skinning::{self, Skin, MAX_JOINTS};
+use super::tangents;
+use crate::logger;
 use glam::{Mat4, Vec3};
 use gltf::buffer::Data;
-use gltf::image::Format;
 use gltf::mesh::util::indices;
+use gltf::Document;
 use gltf::Gltf;
-use gltf::{import_slice, Document};
 use gltf_json::material;
 use std::num::NonZeroU32;
 use std::primitive;
 use wasm_bindgen::JsValue;
-use web_sys::console;
 use wgpu::util::DeviceExt;
 use wgpu::BindGroup;
 
 pub struct ModelExec {}
 
-impl ModelExec {
-    pub async fn new() -> Self {
-        ModelExec {}
+// MaterialPBR::alpha_mode of a glTF material
+fn alpha_mode(material: &gltf::Material) -> u32 {
+    match material.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => CustomStructs::ALPHA_OPAQUE,
+        gltf::material::AlphaMode::Mask => CustomStructs::ALPHA_MASK,
+        gltf::material::AlphaMode::Blend => CustomStructs::ALPHA_BLEND,
     }
-    pub async fn parse_gltf_n_glb(&self, gltf_bytes: &[u8]) -> gltf::Gltf {
-        let model = Gltf::from_slice(gltf_bytes).map_err(|e| e.to_string());
-        let (document, buffers, images) =
-            gltf::import_slice(gltf_bytes).expect("Failed to parse GLB");
-        match &model {
-            Ok(m) => {
-                console::log_1(&"model parsed faltu!".into());
-            }
-            Err(e) => {
-                console::log_1(&format!("model faltu parsing failed: {:?}", e).into());
+}
+
+// Color spaces an image is sampled in by the material slots that use it
+#[derive(Clone, Copy, Default)]
+struct ImageColorSpaces {
+    // base color and emissive
+    srgb: bool,
+    // metallic-roughness, normal and occlusion
+    linear: bool,
+}
+
+fn image_color_spaces(document: &Document) -> Vec<ImageColorSpaces> {
+    let mut spaces = vec![ImageColorSpaces::default(); document.images().count()];
+    let mut mark = |texture: Option<gltf::Texture>, srgb: bool| {
+        if let Some(texture) = texture {
+            let space = &mut spaces[texture.source().index()];
+            if srgb {
+                space.srgb = true;
+            } else {
+                space.linear = true;
             }
         }
-        model.unwrap()
+    };
+    for mat in document.materials() {
+        let pbr = mat.pbr_metallic_roughness();
+        mark(pbr.base_color_texture().map(|t| t.texture()), true);
+        mark(mat.emissive_texture().map(|t| t.texture()), true);
+        mark(pbr.metallic_roughness_texture().map(|t| t.texture()), false);
+        mark(mat.normal_texture().map(|t| t.texture()), false);
+        mark(mat.occlusion_texture().map(|t| t.texture()), false);
     }
-    fn is_Model_PBR(&self, document: &gltf::Document) -> bool {
-        for material in document.materials() {
-            let pbr = material.pbr_metallic_roughness();
-            let has_base_color_texture = pbr.base_color_texture().is_some();
-            let has_metallic_texture = pbr.metallic_roughness_texture().is_some();
-            let metallic_non_default = pbr.metallic_factor() != 1.0;
-            let roughness_non_default = pbr.roughness_factor() != 1.0;
-            if has_base_color_texture
-                || has_metallic_texture
-                || metallic_non_default
-                || roughness_non_default
-            {
-                return true;
-            }
+    spaces
+}
+
+// Sampler for a glTF texture from its sampler's wrap modes and filters. Unset filters default to
+// trilinear, which also gets anisotropic filtering (wgpu leaves it off where unsupported).
+fn create_gltf_sampler(device: &wgpu::Device, texture: &gltf::Texture) -> wgpu::Sampler {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    let sampler = texture.sampler();
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+        Some(MagFilter::Linear) | None => wgpu::FilterMode::Linear,
+    };
+    // (min filter, mipmap filter, samples the mip chain)
+    let (min_filter, mipmap_filter, mipmapped) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, false),
+        Some(MinFilter::Linear) => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, false),
+        Some(MinFilter::NearestMipmapNearest) => {
+            (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, true)
+        }
+        Some(MinFilter::LinearMipmapNearest) => {
+            (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, true)
         }
-        return false;
+        Some(MinFilter::NearestMipmapLinear) => {
+            (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear, true)
+        }
+        Some(MinFilter::LinearMipmapLinear) | None => {
+            (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear, true)
+        }
+    };
+    // anisotropy is only valid with every filter linear
+    let trilinear = mag_filter == wgpu::FilterMode::Linear
+        && min_filter == wgpu::FilterMode::Linear
+        && mipmap_filter == wgpu::FilterMode::Linear;
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some(&format!("Sampler {}", texture.index())),
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+        // filters without a mipmap mode only read the base level
+        lod_max_clamp: if mipmapped { 32.0 } else { 0.0 },
+        anisotropy_clamp: if trilinear { 16 } else { 1 },
+        ..Default::default()
+    })
+}
+
+impl ModelExec {
+    pub async fn new() -> Self {
+        ModelExec {}
+    }
+    pub async fn parse_gltf_n_glb(&self, gltf_bytes: &[u8]) -> Result<gltf::Gltf, RendererError> {
+        let model =
+            Gltf::from_slice(gltf_bytes).map_err(|e| RendererError::GltfParse(e.to_string()))?;
+        logger::log("model parsed faltu!");
+        Ok(model)
     }
     pub async fn build_model(
         &self,
         gltf_bytes: &[u8],
+        scene_index: Option<usize>,
         device: wgpu::Device,
         queue: wgpu::Queue,
-    ) -> (
-        bool,
-        CustomStructs::Model,
-        Vec<CustomStructs::Material>,
-        Vec<CustomStructs::MaterialPBR>,
-        CustomStructs::Texture,
-    ) {
+        // Rgba16Float is renderable and filterable, 16-bit and float images keep their precision
+        half_float_textures: bool,
+    ) -> Result<
+        (
+            CustomStructs::Model,
+            Vec<CustomStructs::MaterialPBR>,
+            CustomStructs::Texture,
+        ),
+        RendererError,
+    > {
         // --- Parse glb ---
-        let parsed_glb: Result<(Document, Vec<Data>, Vec<gltf::image::Data>), gltf::Error> =
-            gltf::import_slice(gltf_bytes);
-
-        let (document, buffers, images) = match parsed_glb {
-            Ok((doc, buf, img)) => {
-                console::log_1(&"parsed glb file".into());
-                (doc, buf, img)
-            }
-            Err(e) => {
-                console::log_1(&format!("error parsing glb: {:?}", e).into());
-                panic!("Failed to parse GLB file");
-            }
-        };
-        let isPbr = self.is_Model_PBR(&document);
-        console::log_1(&format!("isPbr: {:?}", isPbr).into());
+        let Gltf { document, blob } =
+            Gltf::from_slice(gltf_bytes).map_err(|e| RendererError::GltfParse(e.to_string()))?;
+        let buffers = gltf::import_buffers(&document, None, blob)
+            .map_err(|e| RendererError::GltfParse(e.to_string()))?;
+        let images = document
+            .images()
+            .map(|image| image_data::decode_gltf_image(&image, &buffers))
+            .collect::<Result<Vec<_>, _>>()?;
+        logger::log("parsed glb file");
+        let limits = device.limits();
+        self.print_gltf(&document);
 
         // --- Geometry ---
         let mut vertices: Vec<CustomStructs::Vertex> = Vec::new();
         let mut indices: Vec<u32> = Vec::new();
+        let mut primitives: Vec<CustomStructs::Primitive> = Vec::new();
+        let mut morph_deltas = MorphDeltas::default();
+        let mut meshes: Vec<CustomStructs::Mesh> = Vec::new();
 
         let mut meshC = 0;
         let mut primitiveC = 0;
         for mesh in document.meshes() {
-            console::log_1(&format!("mesh is : {:?}", meshC).into());
+            logger::log(&format!("mesh is : {:?}", meshC));
             meshC += 1;
+            meshes.push(CustomStructs::Mesh {
+                name: mesh.name().map(str::to_string),
+                morph_targets: mesh
+                    .primitives()
+                    .map(|primitive| primitive.morph_targets().count())
+                    .max()
+                    .unwrap_or(0),
+            });
             for primitive in mesh.primitives() {
-                console::log_1(&format!("primitive is : {:?}", primitiveC).into());
+                logger::log(&format!("primitive is : {:?}", primitiveC));
                 primitiveC += 1;
                 let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-                console::log_1(&format!("reader is : {:?}", "?").into());
+                logger::log(&format!("reader is : {:?}", "?"));
                 // positions
                 let positions: Vec<[f32; 3]> = reader
                     .read_positions()
                     .map(|iter| iter.collect())
                     .unwrap_or_default();
-                console::log_1(&format!("position is : {:?}", "?").into());
+                logger::log(&format!("position is : {:?}", "?"));
                 // normals
                 let normals: Vec<[f32; 3]> = reader
                     .read_normals()
                     .map(|iter| iter.collect())
                     .unwrap_or_default();
                 // tangents
-                let tangents: Vec<[f32; 4]> = reader
+                let mut tangents: Vec<[f32; 4]> = reader
                     .read_tangents()
                     .map(|iter| iter.collect())
                     .unwrap_or_default();
 
-                console::log_1(&format!("tangent is : {:?}", "?").into());
+                logger::log(&format!("tangent is : {:?}", "?"));
                 let uvs: Vec<[f32; 2]> = reader
                     .read_tex_coords(0)
                     .map(|tc| tc.into_f32().collect())
                     .unwrap_or_default();
-                console::log_1(&format!("uv is : {:?}", "?").into());
+                logger::log(&format!("uv is : {:?}", "?"));
                 let colors: Vec<[f32; 4]> = reader
                     .read_colors(0)
                     .map(|c| c.into_rgba_f32().collect())
                     .unwrap_or_default();
-                console::log_1(&format!("color is : {:?}", "?").into());
+                logger::log(&format!("color is : {:?}", "?"));
                 let joints: Vec<[u32; 4]> = reader
                     .read_joints(0)
                     .map(|j| {
@@ -129,12 +215,12 @@ impl ModelExec {
                             .collect()
                     })
                     .unwrap_or_default();
-                console::log_1(&format!("joint is : {:?}", "?").into());
+                logger::log(&format!("joint is : {:?}", "?"));
                 let weights: Vec<[f32; 4]> = reader
                     .read_weights(0)
                     .map(|w| w.into_f32().collect())
                     .unwrap_or_default();
-                console::log_1(&format!("weight is : {:?}", "?").into());
+                logger::log(&format!("weight is : {:?}", "?"));
                 // indices
                 let primitive_indices: Vec<u32> = if let Some(read_indices) = reader.read_indices()
                 {
@@ -148,23 +234,61 @@ impl ModelExec {
                 } else {
                     (0..positions.len() as u32).collect()
                 };
+                // normal maps need a tangent frame, generate one when the glTF has none
+                let normal_mapped = primitive.material().normal_texture().is_some();
+                if tangents.is_empty()
+                    && normal_mapped
+                    && primitive.mode() == gltf::mesh::Mode::Triangles
+                    && !normals.is_empty()
+                    && !uvs.is_empty()
+                {
+                    tangents = tangents::angle_weighted_tangents(
+                        &positions,
+                        &normals,
+                        &uvs,
+                        &primitive_indices,
+                    );
+                }
+                // blend shapes, deltas for the vertex stage
+                let targets: Vec<TargetDeltas> = reader
+                    .read_morph_targets()
+                    .map(|(positions, normals, tangents)| TargetDeltas {
+                        positions: positions.map(|p| p.collect()).unwrap_or_default(),
+                        normals: normals.map(|n| n.collect()).unwrap_or_default(),
+                        tangents: tangents.map(|t| t.collect()).unwrap_or_default(),
+                    })
+                    .collect();
+                let morph_base = morph_deltas.push(&targets, positions.len());
                 let index_offset = vertices.len() as u32;
+                primitives.push(CustomStructs::Primitive {
+                    mesh_index: mesh.index(),
+                    first_index: indices.len() as u32,
+                    index_count: primitive_indices.len() as u32,
+                    material_index: primitive.material().index(),
+                    bounds: {
+                        let bounds = primitive.bounding_box();
+                        (Vec3::from_array(bounds.min), Vec3::from_array(bounds.max))
+                    },
+                    alpha_mode: alpha_mode(&primitive.material()),
+                    double_sided: primitive.material().double_sided(),
+                    first_vertex: index_offset,
+                    vertex_count: positions.len() as u32,
+                    morph_targets: targets.len() as u32,
+                    morph_base,
+                });
                 indices.extend(primitive_indices.iter().map(|i| i + index_offset));
-                console::log_1(
-                    &format!(
-                        "indices is {:?} and positions.len():  {:?}",
-                        indices.len(),
-                        positions.len()
-                    )
-                    .into(),
-                );
-                console::log_1(&format!("positions length : {:?}", positions.len()).into());
-                console::log_1(&format!("normal length : {:?}", normals.len()).into());
-                console::log_1(&format!("tangent length : {:?}", tangents.len()).into());
-                console::log_1(&format!("uv length : {:?}", uvs.len()).into());
-                console::log_1(&format!("color length : {:?}", colors.len()).into());
-                console::log_1(&format!("joints length : {:?}", joints.len()).into());
-                console::log_1(&format!("weights length : {:?}", weights.len()).into());
+                logger::log(&format!(
+                    "indices is {:?} and positions.len():  {:?}",
+                    indices.len(),
+                    positions.len()
+                ));
+                logger::log(&format!("positions length : {:?}", positions.len()));
+                logger::log(&format!("normal length : {:?}", normals.len()));
+                logger::log(&format!("tangent length : {:?}", tangents.len()));
+                logger::log(&format!("uv length : {:?}", uvs.len()));
+                logger::log(&format!("color length : {:?}", colors.len()));
+                logger::log(&format!("joints length : {:?}", joints.len()));
+                logger::log(&format!("weights length : {:?}", weights.len()));
                 for i in 0..positions.len() {
                     vertices.push(CustomStructs::Vertex {
                         position: positions[i],
@@ -178,7 +302,86 @@ impl ModelExec {
                 }
             }
         }
+        // --- Scene: every node that instances a mesh draws all of its primitives ---
+        let scene = SceneGraph::from_document(&document, scene_index)?;
+        let mut draws: Vec<CustomStructs::Draw> = Vec::new();
+        for (node, mesh) in scene.mesh_instances() {
+            for (primitive, _) in primitives
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.mesh_index == mesh)
+            {
+                draws.push(CustomStructs::Draw {
+                    node,
+                    primitive,
+                    skin: scene.nodes[node].skin,
+                });
+            }
+        }
+        let animations: Vec<Clip> = document
+            .animations()
+            .map(|animation| Clip::from_gltf(&animation, &buffers))
+            .collect();
+        let skins: Vec<Skin> = document
+            .skins()
+            .map(|skin| Skin::from_gltf(&skin, &buffers))
+            .collect();
+        for (i, skin) in skins.iter().enumerate() {
+            if skin.joints.len() > MAX_JOINTS {
+                logger::log(&format!(
+                    "skin {} has {} joints, only the first {} are used",
+                    i,
+                    skin.joints.len(),
+                    MAX_JOINTS
+                ));
+            }
+        }
+        let gltf_lights: Vec<gltf::khr_lights_punctual::Light> = document
+            .lights()
+            .map(|lights| lights.collect())
+            .unwrap_or_default();
+        let lights: Vec<(usize, Light)> = scene
+            .light_instances()
+            .into_iter()
+            .filter_map(|(node, light)| Some((node, Light::from_gltf(gltf_lights.get(light)?))))
+            .collect();
+        if lights.len() > MAX_LIGHTS {
+            logger::log(&format!(
+                "{} lights in the scene, only the first {} are used",
+                lights.len(),
+                MAX_LIGHTS
+            ));
+        }
+        logger::log(&format!(
+            "scene nodes: {:?}, draws: {:?}, lights: {:?}, skins: {:?}, animations: {:?}",
+            scene.nodes.len(),
+            draws.len(),
+            lights.len(),
+            skins.len(),
+            animations.len()
+        ));
+
         // --- Upload to GPU buffers ---
+        RendererError::check_limit(
+            "vertex buffer size",
+            std::mem::size_of_val(vertices.as_slice()) as u64,
+            limits.max_buffer_size,
+        )?;
+        RendererError::check_limit(
+            "index buffer size",
+            std::mem::size_of_val(indices.as_slice()) as u64,
+            limits.max_buffer_size,
+        )?;
+        RendererError::check_limit(
+            "camera uniform buffer size",
+            CustomStructs::CameraUniform::aligned_size(&device) * draws.len().max(1) as u64,
+            limits.max_buffer_size,
+        )?;
+        RendererError::check_limit(
+            "joint uniform buffer size",
+            std::mem::size_of::<CustomStructs::JointsUniform>() as u64 * skins.len().max(1) as u64,
+            limits.max_buffer_size,
+        )?;
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(&vertices),
@@ -191,266 +394,142 @@ impl ModelExec {
             usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
         });
 
+        let morph_deltas = morph_deltas.upload(&device, &queue)?;
+
         let model: CustomStructs::Model = CustomStructs::Model {
             vertex_buffer,
             indice_buffer,
             indice_count: indices.len() as u32,
+            primitives,
+            scene,
+            draws,
+            lights,
+            skins,
+            animations,
+            meshes,
+            morph_deltas,
         };
-        // --- Materials ---
-        let mut materials: Vec<CustomStructs::Material> = Vec::new();
+        // --- Materials (one per glTF material, primitives without one use the default) ---
         let mut materialPbr: Vec<CustomStructs::MaterialPBR> = Vec::new();
-        if isPbr {
-            console::log_1(&"r1".into());
-            for mat in document.materials() {
-                let pbr = mat.pbr_metallic_roughness();
-                let alpha_mode = match mat.alpha_mode() {
-                    gltf::material::AlphaMode::Opaque => 0,
-                    gltf::material::AlphaMode::Mask => 1,
-                    gltf::material::AlphaMode::Blend => 2,
-                };
-                console::log_1(&"r2".into());
-                // Lookup texture indices (if available)
-                let base_color_tex = pbr.base_color_texture().map(|t| t.texture().index() as u32);
-                let metallic_roughness_tex = pbr
-                    .metallic_roughness_texture()
-                    .map(|t| t.texture().index() as u32);
-                let normal_tex = mat.normal_texture().map(|t| t.texture().index() as u32);
-                let occlusion_tex = mat.occlusion_texture().map(|t| t.texture().index() as u32);
-                let emissive_tex = mat.emissive_texture().map(|t| t.texture().index() as u32);
-
-                // Fill struct
-                let material: CustomStructs::MaterialPBR = CustomStructs::MaterialPBR {
-                    base_color_factor: pbr.base_color_factor(),
-                    metallic_factor: pbr.metallic_factor(),
-                    roughness_factor: pbr.roughness_factor(),
-                    emissive_factor: mat.emissive_factor(),
-
-                    base_color_texture: base_color_tex.unwrap_or(0),
-                    metallic_roughness_texture: metallic_roughness_tex.unwrap_or(0),
-                    normal_texture: normal_tex.unwrap_or(0),
-                    occlusion_texture: occlusion_tex.unwrap_or(0),
-                    emissive_texture: emissive_tex.unwrap_or(0),
-
-                    alpha_cutoff: mat.alpha_cutoff().unwrap_or(0.5),
-                    alpha_mode,
-                    double_sided: if mat.double_sided() { 1 } else { 0 },
-                    _padding: [0],
-                };
-
-                console::log_1(&"r3".into());
-                materialPbr.push(material);
-            }
-        } else {
-            for mat in document.materials() {
-                let base_color_factor = [1.0, 1.0, 1.0, 1.0]; // pure white
-
-                let alpha_mode = match mat.alpha_mode() {
-                    gltf::material::AlphaMode::Opaque => 0,
-                    gltf::material::AlphaMode::Mask => 1,
-                    gltf::material::AlphaMode::Blend => 2,
-                };
-
-                materials.push(CustomStructs::Material {
-                    base_color_factor,
-                    alpha_cutoff: 0.0, // ignore mask cutoff
-                    alpha_mode,
-                    double_sided: 0, // default single-sided
-                    _padding: [0],
-                });
-
-                // Base Color
-                if let Some(tex) = mat.normal_texture() {
-                    console::log_1(
-                        &format!("NormalTexture: index {}", tex.texture().index()).into(),
-                    );
-                }
-
-                // Metallic-Roughness
-                let pbr = mat.pbr_metallic_roughness();
-
-                // Base Color
-                if let Some(tex) = pbr.base_color_texture() {
-                    console::log_1(
-                        &format!("BaseColorTexture: index {}", tex.texture().index()).into(),
-                    );
-                }
-
-                if let Some(tex) = pbr.metallic_roughness_texture() {
-                    console::log_1(
-                        &format!("MetallicRoughnessTexture: index {}", tex.texture().index())
-                            .into(),
-                    );
-                }
-
-                // Normal map
-                if let Some(tex) = mat.normal_texture() {
-                    console::log_1(
-                        &format!("NormalTexture: index {}", tex.texture().index()).into(),
-                    );
-                }
-
-                // Occlusion
-                if let Some(tex) = mat.occlusion_texture() {
-                    console::log_1(
-                        &format!("OcclusionTexture: index {}", tex.texture().index()).into(),
-                    );
-                }
-
-                // Emissive
-                if let Some(tex) = mat.emissive_texture() {
-                    console::log_1(
-                        &format!("EmissiveTexture: index {}", tex.texture().index()).into(),
-                    );
-                }
+        for mat in document.materials() {
+            let pbr = mat.pbr_metallic_roughness();
+            let alpha_mode = alpha_mode(&mat);
+            // Lookup texture indices (if available)
+            let base_color_tex = pbr.base_color_texture().map(|t| t.texture().index() as u32);
+            let metallic_roughness_tex = pbr
+                .metallic_roughness_texture()
+                .map(|t| t.texture().index() as u32);
+            let normal_tex = mat.normal_texture().map(|t| t.texture().index() as u32);
+            let occlusion_tex = mat.occlusion_texture().map(|t| t.texture().index() as u32);
+            let emissive_tex = mat.emissive_texture().map(|t| t.texture().index() as u32);
+
+            // Fill struct
+            let material: CustomStructs::MaterialPBR = CustomStructs::MaterialPBR {
+                base_color_factor: pbr.base_color_factor(),
+                metallic_factor: pbr.metallic_factor(),
+                roughness_factor: pbr.roughness_factor(),
+                emissive_factor: mat.emissive_factor(),
+
+                base_color_texture: base_color_tex.unwrap_or(CustomStructs::NO_TEXTURE),
+                metallic_roughness_texture: metallic_roughness_tex
+                    .unwrap_or(CustomStructs::NO_TEXTURE),
+                normal_texture: normal_tex.unwrap_or(CustomStructs::NO_TEXTURE),
+                occlusion_texture: occlusion_tex.unwrap_or(CustomStructs::NO_TEXTURE),
+                emissive_texture: emissive_tex.unwrap_or(CustomStructs::NO_TEXTURE),
+
+                alpha_cutoff: mat.alpha_cutoff().unwrap_or(0.5),
+                alpha_mode,
+                double_sided: if mat.double_sided() { 1 } else { 0 },
+                normal_scale: mat.normal_texture().map_or(1.0, |t| t.scale()),
+                _padding: [0; 2],
+            };
 
-                console::log_1(&"Factors:".into());
-                console::log_1(&format!("  BaseColorFactor: {:?}", pbr.base_color_factor()).into());
-                console::log_1(&format!("  MetallicFactor: {}", pbr.metallic_factor()).into());
-                console::log_1(&format!("  RoughnessFactor: {}", pbr.roughness_factor()).into());
-                console::log_1(&format!("  EmissiveFactor: {:?}", mat.emissive_factor()).into());
-            }
+            materialPbr.push(material);
         }
 
-        const MAX_TEXTURES: usize = 16;
-        // --- Textures ---
-        let mut textures: CustomStructs::Texture = CustomStructs::Texture {
-            textures: Vec::new(),
-            views: Vec::new(),
-            samplers: Vec::new(),
-        };
+        // --- Images (uploaded once per color space, shared by every texture that samples them) ---
+        let color_spaces = image_color_spaces(&document);
+        let mut srgb_views: Vec<Option<wgpu::TextureView>> = Vec::new();
+        let mut linear_views: Vec<Option<wgpu::TextureView>> = Vec::new();
+        let mut mip_generator = MipGenerator::new(&device);
 
         for (i, image) in images.iter().enumerate() {
-            if i >= MAX_TEXTURES {
-                break; // clamp to fragment shader array size
+            logger::log(&format!("image format: {:?}", image.format));
+            let ImageColorSpaces { srgb, linear } = color_spaces[i];
+            if !srgb && !linear {
+                // not sampled by any material slot
+                srgb_views.push(None);
+                linear_views.push(None);
+                continue;
             }
-            console::log_1(&format!("image format: {:?}", image.format).into());
-            let mut rgba_pixels = Vec::with_capacity((image.width * image.height * 4) as usize);
-            match image.format {
-                Format::R8G8B8 => {
-                    for chunk in image.pixels.chunks(3) {
-                        rgba_pixels.push(chunk[0]);
-                        rgba_pixels.push(chunk[1]);
-                        rgba_pixels.push(chunk[2]);
-                        rgba_pixels.push(255); // opaque alpha
-                    }
-                }
-                Format::R8G8B8A8 => {
-                    for chunk in image.pixels.chunks(4) {
-                        rgba_pixels.extend_from_slice(chunk);
-                    }
-                }
-                _ => panic!("Unsupported image format: {:?}", image.format),
-            }
-
-            console::log_1(&format!("rgba_pixels: {:?}", rgba_pixels.len()).into());
-
-            // Compute padded bytes_per_row for WebGPU (must be multiple of 256)
-            let bytes_per_row = (((4 * image.width as u32 + 255) / 256) * 256) as usize;
-
-            // Create padded buffer
-            let mut padded_data = vec![0u8; bytes_per_row * image.height as usize];
-            for y in 0..image.height {
-                let src_start = (y as usize * image.width as usize * 4);
-                let src_end = src_start + (image.width as usize * 4);
-                let dst_start = y as usize * bytes_per_row;
-                let dst_end = dst_start + (image.width as usize * 4);
-
-                padded_data[dst_start..dst_end].copy_from_slice(&rgba_pixels[src_start..src_end]);
-            }
-
+            RendererError::check_limit(
+                "texture dimension",
+                image.width.max(image.height) as u64,
+                limits.max_texture_dimension_2d as u64,
+            )?;
+            let pixels = ImagePixels::from_gltf(image);
             let tex_size = wgpu::Extent3d {
                 width: image.width as u32,
                 height: image.height as u32,
                 depth_or_array_layers: 1,
             };
 
-            let texture = device.create_texture(&wgpu::TextureDescriptor {
-                label: Some(&format!("Texture {}", i)),
-                size: tex_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            });
-
-            queue.write_texture(
-                wgpu::TexelCopyTextureInfo {
-                    texture: &texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
-                    aspect: wgpu::TextureAspect::All,
-                },
-                &padded_data,
-                wgpu::TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(bytes_per_row as u32),
-                    rows_per_image: Some(image.height as u32),
-                },
-                tex_size,
-            );
-
-            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-                label: Some(&format!("Sampler {}", i)),
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Linear,
-                mipmap_filter: wgpu::FilterMode::Linear,
-                ..Default::default()
-            });
-
-            textures.textures.push(texture);
-            textures.views.push(view);
-            textures.samplers.push(sampler);
-        }
-        // Fill remaining texture slots with 1x1 white dummy textures
-        while textures.textures.len() < MAX_TEXTURES {
-            let tex_size = wgpu::Extent3d {
-                width: 1,
-                height: 1,
-                depth_or_array_layers: 1,
+            // WebGL2 can't view one texture in another format, so an image sampled as both
+            // color and data is uploaded twice
+            let mut upload = |srgb: bool| {
+                let (format, texels) = pixels.texture_data(srgb, half_float_textures);
+                let bytes_per_pixel = format.block_copy_size(None).unwrap_or(4);
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some(&format!("Image {} {:?}", i, format)),
+                    size: tex_size,
+                    mip_level_count: mipmaps::mip_level_count(tex_size.width, tex_size.height),
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    // the mip chain is rendered from level 0
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_DST
+                        | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                });
+                queue.write_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d::ZERO,
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    &texels,
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(tex_size.width * bytes_per_pixel),
+                        rows_per_image: Some(image.height as u32),
+                    },
+                    tex_size,
+                );
+                mip_generator.generate(&device, &queue, &texture);
+                texture.create_view(&wgpu::TextureViewDescriptor::default())
             };
-            let texture = device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("Dummy Texture"),
-                size: tex_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            });
-
-            let white_pixel = [255u8, 255, 255, 255];
-            queue.write_texture(
-                wgpu::TexelCopyTextureInfo {
-                    texture: &texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
-                    aspect: wgpu::TextureAspect::All,
-                },
-                &white_pixel,
-                wgpu::TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(4),
-                    rows_per_image: Some(1),
-                },
-                tex_size,
-            );
+            srgb_views.push(srgb.then(|| upload(true)));
+            linear_views.push(linear.then(|| upload(false)));
+        }
 
-            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-            let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        // --- Textures (indexed like glTF `texture().index()`) ---
+        let mut textures: CustomStructs::Texture = CustomStructs::Texture {
+            srgb_views: Vec::new(),
+            linear_views: Vec::new(),
+            samplers: Vec::new(),
+        };
+        for texture in document.textures() {
+            let source = texture.source().index();
+            let sampler = create_gltf_sampler(&device, &texture);
 
-            textures.textures.push(texture);
-            textures.views.push(view);
+            textures.srgb_views.push(srgb_views[source].clone());
+            textures.linear_views.push(linear_views[source].clone());
             textures.samplers.push(sampler);
         }
-        console::log_1(&"model_build run successful".into());
-        (isPbr, model, materials, materialPbr, textures)
+        logger::log("model_build run successful");
+        Ok((model, materialPbr, textures))
     }
 
     pub fn extract_indices(gltf: &Gltf, buffers: &Vec<Data>) -> Vec<u32> {
@@ -494,16 +573,44 @@ impl ModelExec {
         // Group 0: Camera
         let camera_bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Camera BGL"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true, // one slot per draw
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<
+                            CustomStructs::CameraUniform,
+                        >() as u64),
+                    },
+                    count: None,
+                },
+                // joint palette of the draw's skin
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true, // one slot per skin
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<
+                            CustomStructs::JointsUniform,
+                        >() as u64),
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                // morph target deltas, read with textureLoad
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
         });
 
         // Group 1: Material uniforms + textures (5 textures + 5 samplers)
@@ -609,37 +716,80 @@ impl ModelExec {
             ],
         });
 
-        // Group 2: Directional light (optional, can be simplified for now)
+        // Group 2: punctual lights (LightsUniform) and the key light's shadow map (see shadows.rs)
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         let light_bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Light BGL"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
+            entries: &[
+                uniform_entry(0),
+                uniform_entry(1),
+                shadows::map_entry(2),
+                shadows::sampler_entry(3),
+            ],
         });
 
         (camera_bgl, material_bgl, light_bgl)
     }
 
-    // Camera buffer
-    fn buffer_cameraUniform_pbr(&self, device: &wgpu::Device) -> wgpu::Buffer {
-        let rotation = Mat4::from_rotation_y(std::f32::consts::PI / 4.0);
+    // One CameraUniform per draw, each at its own dynamic offset with that draw's node matrix
+    // (the identity for skinned draws, see Model::draw_matrix) and morph target weights
+    pub fn camera_uniform_bytes(
+        &self,
+        device: &wgpu::Device,
+        model: &CustomStructs::Model,
+        view_proj: Mat4,
+        camera_pos: Vec3,
+    ) -> Vec<u8> {
+        let stride = CustomStructs::CameraUniform::aligned_size(device) as usize;
+        let mut bytes = vec![0u8; stride * model.draws.len().max(1)];
+        for (i, draw) in model.draws.iter().enumerate() {
+            let primitive = &model.primitives[draw.primitive];
+            let (morph_weights, morph_indices, morph_count) = morph_targets::active_targets(
+                &model.scene.nodes[draw.node].weights,
+                primitive.morph_targets as usize,
+            );
+            let uniforms = CustomStructs::CameraUniform {
+                view_proj: view_proj.to_cols_array_2d(),
+                model: model.draw_matrix(draw).to_cols_array_2d(),
+                camera_pos: camera_pos.to_array(),
+                skinned: draw.skin.is_some() as u32,
+                morph_weights,
+                morph_indices,
+                morph_base: primitive.morph_base,
+                morph_vertex_start: primitive.first_vertex,
+                morph_vertex_count: primitive.vertex_count,
+                morph_count,
+            };
+            let slot = bytemuck::bytes_of(&uniforms);
+            bytes[i * stride..i * stride + slot.len()].copy_from_slice(slot);
+        }
+        bytes
+    }
 
-        let uniforms = CustomStructs::CameraUniform {
-            view_proj: glam::Mat4::IDENTITY.to_cols_array_2d(),
-            model: glam::Mat4::IDENTITY.to_cols_array_2d(),
-            camera_pos: [1.0, 1.0, 0.0], // initial camera position
-            _pad: 0.0,                   // padding
-        };
+    // Camera buffer
+    fn buffer_cameraUniform_pbr(
+        &self,
+        device: &wgpu::Device,
+        model: &CustomStructs::Model,
+    ) -> wgpu::Buffer {
+        let contents = self.camera_uniform_bytes(
+            device,
+            model,
+            Mat4::IDENTITY,
+            Vec3::new(1.0, 1.0, 0.0), // initial camera position
+        );
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[uniforms]),
+            contents: &contents,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         })
     }
@@ -657,26 +807,22 @@ impl ModelExec {
         })
     }
 
-    // Light buffer (simple directional light)
+    // Light buffer, rewritten every frame with the scene's and the API's lights
     fn buffer_light_pbr(&self, device: &wgpu::Device) -> wgpu::Buffer {
-        let light = CustomStructs::LightUniform {
-            direction: [0.0, -1.0, 0.0],
-            _pad1: 0.0,
-            color: [0.0, 0.0, 0.0],
-            _pad2: 0.0,
-        };
+        let lights = lights_uniform([]);
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Light Buffer"),
-            contents: bytemuck::cast_slice(&[light]),
+            contents: bytemuck::bytes_of(&lights),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         })
     }
 
-    pub async fn create_depth_texture(
+    pub fn create_depth_texture(
         &self,
         device: &wgpu::Device,
         width: u32,
         height: u32,
+        sample_count: u32,
     ) -> wgpu::TextureView {
         let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Depth Texture"),
@@ -686,7 +832,7 @@ impl ModelExec {
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Depth24Plus,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -702,13 +848,17 @@ impl ModelExec {
         camera_bgl: &wgpu::BindGroupLayout,
         material_bgl: &wgpu::BindGroupLayout,
         light_bgl: &wgpu::BindGroupLayout,
+        environment_bgl: &wgpu::BindGroupLayout,
         vertShader: &wgpu::ShaderModule,
         fragPbrShader: &wgpu::ShaderModule,
+        sample_count: u32,
+        alpha_mode: u32,
+        double_sided: bool,
     ) -> wgpu::RenderPipeline {
         // Create pipeline layout
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("PBR Pipeline Layout"),
-            bind_group_layouts: &[camera_bgl, material_bgl, light_bgl],
+            bind_group_layouts: &[camera_bgl, material_bgl, light_bgl, environment_bgl],
             push_constant_ranges: &[],
         });
         // vertex layout
@@ -718,50 +868,59 @@ impl ModelExec {
             attributes: &[
                 // POSITION @location(0)
                 wgpu::VertexAttribute {
-                    offset: 0,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, position)
+                        as wgpu::BufferAddress,
                     shader_location: 0,
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 // NORMAL @location(1)
                 wgpu::VertexAttribute {
-                    offset: 12,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, normal)
+                        as wgpu::BufferAddress,
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 // TANGENT @location(2)
                 wgpu::VertexAttribute {
-                    offset: 24,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, tangent)
+                        as wgpu::BufferAddress,
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x4,
                 },
                 // TEXCOORD_0 @location(3)
                 wgpu::VertexAttribute {
-                    offset: 40,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, uv) as wgpu::BufferAddress,
                     shader_location: 3,
                     format: wgpu::VertexFormat::Float32x2,
                 },
                 // COLOR_0 @location(4)
                 wgpu::VertexAttribute {
-                    offset: 48,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, color)
+                        as wgpu::BufferAddress,
                     shader_location: 4,
                     format: wgpu::VertexFormat::Float32x4,
                 },
                 // JOINTS_0 @location(5) (u32x4)
                 wgpu::VertexAttribute {
-                    offset: 64,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, joints)
+                        as wgpu::BufferAddress,
                     shader_location: 5,
                     format: wgpu::VertexFormat::Uint32x4,
                 },
                 // WEIGHTS_0 @location(6)
                 wgpu::VertexAttribute {
-                    offset: 80,
+                    offset: std::mem::offset_of!(CustomStructs::Vertex, weights)
+                        as wgpu::BufferAddress,
                     shader_location: 6,
                     format: wgpu::VertexFormat::Float32x4,
                 },
             ],
         }];
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("PBR Render Pipeline"),
+            label: Some(&format!(
+                "PBR Render Pipeline (alpha mode {}, double sided {})",
+                alpha_mode, double_sided
+            )),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
                 module: vertShader,
@@ -777,7 +936,10 @@ impl ModelExec {
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
                     format: render_format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    // the shader writes premultiplied color; blended materials composite "over"
+                    // what is behind them, opaque and masked ones replace it
+                    blend: (alpha_mode == CustomStructs::ALPHA_BLEND)
+                        .then_some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
                 compilation_options: wgpu::PipelineCompilationOptions {
@@ -789,20 +951,21 @@ impl ModelExec {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
+                cull_mode: (!double_sided).then_some(wgpu::Face::Back),
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: wgpu::TextureFormat::Depth24Plus,
-                depth_write_enabled: true,
+                // blended surfaces are sorted instead and must not hide what is drawn after them
+                depth_write_enabled: alpha_mode != CustomStructs::ALPHA_BLEND,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -811,227 +974,391 @@ impl ModelExec {
         })
     }
 
+    // 1x1 texture used for material slots the glTF leaves empty
+    fn default_texture_pbr(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        pixel: [u8; 4],
+        format: wgpu::TextureFormat,
+    ) -> wgpu::TextureView {
+        let size = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &pixel,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4),
+                rows_per_image: Some(1),
+            },
+            size,
+        );
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    // Looks up a material texture slot in the slot's color space, falling back when the slot
+    // is empty
+    fn material_texture<'a>(
+        textures: &'a CustomStructs::Texture,
+        index: u32,
+        srgb: bool,
+        fallback_view: &'a wgpu::TextureView,
+        fallback_sampler: &'a wgpu::Sampler,
+    ) -> (&'a wgpu::TextureView, &'a wgpu::Sampler) {
+        let views = if srgb {
+            &textures.srgb_views
+        } else {
+            &textures.linear_views
+        };
+        match (
+            views.get(index as usize).and_then(Option::as_ref),
+            textures.samplers.get(index as usize),
+        ) {
+            (Some(view), Some(sampler)) if index != CustomStructs::NO_TEXTURE => (view, sampler),
+            _ => (fallback_view, fallback_sampler),
+        }
+    }
+
+    // Returns the camera BG, one material BG per MaterialPBR (in glTF material order)
+    // followed by a default material BG for primitives without a material, the light BG
+    // (lights plus `shadows`' map) and the camera, joint and light uniform buffers so they can
+    // be rewritten every frame.
     pub async fn bindGroupPBR_all(
         &self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        model: &CustomStructs::Model,
         materials: Vec<CustomStructs::MaterialPBR>,
         textures: &CustomStructs::Texture,
         camera_bgl: wgpu::BindGroupLayout,
         material_bgl: wgpu::BindGroupLayout,
         light_bgl: wgpu::BindGroupLayout,
-    ) -> (wgpu::BindGroup, wgpu::BindGroup, wgpu::BindGroup) {
-        //let (camera_bgl, material_bgl, light_bgl) = self.bindGroupLayoutPBR_all(device);
-
-        // === Camera bind group ===
+        shadows: &Shadows,
+    ) -> (
+        wgpu::BindGroup,
+        Vec<wgpu::BindGroup>,
+        wgpu::BindGroup,
+        wgpu::Buffer,
+        wgpu::Buffer,
+        wgpu::Buffer,
+    ) {
+        // === Camera bind group (per draw camera slots, per skin joint palettes, morph deltas) ===
+        let camera_buffer = self.buffer_cameraUniform_pbr(device, model);
+        let joints_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Joints Uniform Buffer"),
+            contents: &skinning::joint_palette_bytes(&model.skins, &model.scene),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
         let camera_bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Camera BG"),
             layout: &camera_bgl,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: self.buffer_cameraUniform_pbr(device).as_entire_binding(),
-            }],
-        });
-
-        // === Material uniform buffer ===
-        let material_buffer = self.buffer_material_pbr(device, materials);
-
-        // === Default 1x1 texture + sampler ===
-        let default_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("DefaultTexture"),
-            size: wgpu::Extent3d {
-                width: 1,
-                height: 1,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
-        let default_view = default_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let default_sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
-
-        // Fill missing textures with default
-        let base_color_tex = textures.views.get(0).unwrap_or(&default_view);
-        let base_color_smp = textures.samplers.get(0).unwrap_or(&default_sampler);
-        let metallic_tex = textures.views.get(1).unwrap_or(&default_view);
-        let metallic_smp = textures.samplers.get(1).unwrap_or(&default_sampler);
-        let normal_tex = textures.views.get(2).unwrap_or(&default_view);
-        let normal_smp = textures.samplers.get(2).unwrap_or(&default_sampler);
-        let occlusion_tex = textures.views.get(3).unwrap_or(&default_view);
-        let occlusion_smp = textures.samplers.get(3).unwrap_or(&default_sampler);
-        let emissive_tex = textures.views.get(4).unwrap_or(&default_view);
-        let emissive_smp = textures.samplers.get(4).unwrap_or(&default_sampler);
-
-        // === Material + textures bind group ===
-        let material_bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Material + Textures BG"),
-            layout: &material_bgl,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: material_buffer.as_entire_binding(),
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &camera_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(
+                            std::mem::size_of::<CustomStructs::CameraUniform>() as u64,
+                        ),
+                    }),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureView(base_color_tex),
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &joints_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(
+                            std::mem::size_of::<CustomStructs::JointsUniform>() as u64,
+                        ),
+                    }),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::Sampler(base_color_smp),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::TextureView(metallic_tex),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::Sampler(metallic_smp),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::TextureView(normal_tex),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::Sampler(normal_smp),
+                    resource: wgpu::BindingResource::TextureView(&model.morph_deltas),
                 },
+            ],
+        });
+
+        // === Default textures + sampler for empty slots ===
+        let white_view = self.default_texture_pbr(
+            device,
+            queue,
+            "Default White Texture",
+            [255, 255, 255, 255],
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        );
+        // flat tangent-space normal (0, 0, 1)
+        let normal_view = self.default_texture_pbr(
+            device,
+            queue,
+            "Default Normal Texture",
+            [128, 128, 255, 255],
+            wgpu::TextureFormat::Rgba8Unorm,
+        );
+        let default_sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        // glTF defaults for primitives that reference no material
+        let default_material = CustomStructs::MaterialPBR {
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            emissive_factor: [0.0, 0.0, 0.0],
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            base_color_texture: CustomStructs::NO_TEXTURE,
+            metallic_roughness_texture: CustomStructs::NO_TEXTURE,
+            normal_texture: CustomStructs::NO_TEXTURE,
+            occlusion_texture: CustomStructs::NO_TEXTURE,
+            emissive_texture: CustomStructs::NO_TEXTURE,
+            alpha_cutoff: 0.5,
+            alpha_mode: 0,
+            double_sided: 0,
+            normal_scale: 1.0,
+            _padding: [0; 2],
+        };
+
+        // === Material + textures bind groups ===
+        let mut material_bgs: Vec<wgpu::BindGroup> = Vec::new();
+        for (i, material) in materials
+            .iter()
+            .chain(std::iter::once(&default_material))
+            .enumerate()
+        {
+            let material_buffer = self.buffer_material_pbr(device, vec![*material]);
+            let (base_color_tex, base_color_smp) = Self::material_texture(
+                textures,
+                material.base_color_texture,
+                true,
+                &white_view,
+                &default_sampler,
+            );
+            let (metallic_tex, metallic_smp) = Self::material_texture(
+                textures,
+                material.metallic_roughness_texture,
+                false,
+                &white_view,
+                &default_sampler,
+            );
+            let (normal_tex, normal_smp) = Self::material_texture(
+                textures,
+                material.normal_texture,
+                false,
+                &normal_view,
+                &default_sampler,
+            );
+            let (occlusion_tex, occlusion_smp) = Self::material_texture(
+                textures,
+                material.occlusion_texture,
+                false,
+                &white_view,
+                &default_sampler,
+            );
+            let (emissive_tex, emissive_smp) = Self::material_texture(
+                textures,
+                material.emissive_texture,
+                true,
+                &white_view,
+                &default_sampler,
+            );
+
+            material_bgs.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&format!("Material {} + Textures BG", i)),
+                layout: &material_bgl,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: material_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(base_color_tex),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(base_color_smp),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(metallic_tex),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Sampler(metallic_smp),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(normal_tex),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::Sampler(normal_smp),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: wgpu::BindingResource::TextureView(occlusion_tex),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 8,
+                        resource: wgpu::BindingResource::Sampler(occlusion_smp),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 9,
+                        resource: wgpu::BindingResource::TextureView(emissive_tex),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 10,
+                        resource: wgpu::BindingResource::Sampler(emissive_smp),
+                    },
+                ],
+            }));
+        }
+
+        // === Light bind group ===
+        let light_buffer = self.buffer_light_pbr(device); // create your light uniform buffer
+        let light_bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light BG"),
+            layout: &light_bgl,
+            entries: &[
                 wgpu::BindGroupEntry {
-                    binding: 7,
-                    resource: wgpu::BindingResource::TextureView(occlusion_tex),
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
-                    binding: 8,
-                    resource: wgpu::BindingResource::Sampler(occlusion_smp),
+                    binding: 1,
+                    resource: shadows.uniform_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
-                    binding: 9,
-                    resource: wgpu::BindingResource::TextureView(emissive_tex),
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadows.map_view),
                 },
                 wgpu::BindGroupEntry {
-                    binding: 10,
-                    resource: wgpu::BindingResource::Sampler(emissive_smp),
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadows.sampler),
                 },
             ],
         });
 
-        // === Light bind group ===
-        let light_buffer = self.buffer_light_pbr(device); // create your light uniform buffer
-        let light_bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Light BG"),
-            layout: &light_bgl,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-        });
-
-        (camera_bg, material_bg, light_bg)
+        (
+            camera_bg,
+            material_bgs,
+            light_bg,
+            camera_buffer,
+            joints_buffer,
+            light_buffer,
+        )
     }
 
-    pub fn print_gltf(&self, model: &gltf::Gltf) {
-        console::log_1(&"========== glTF Contents ==========".into());
-        console::log_1(&format!("Scenes: {:?}", model.scenes().count()).into());
+    pub fn print_gltf(&self, model: &gltf::Document) {
+        logger::log("========== glTF Contents ==========");
+        logger::log(&format!("Scenes: {:?}", model.scenes().count()));
         /*
         for (i, scene) in model.scenes().enumerate() {
-            console::log_1(&format!("Scene {}: {:?}", i, scene.name()).into());
+            logger::log(&format!("Scene {}: {:?}", i, scene.name()));
             for node in scene.nodes() {
                 let pad = " ".repeat(2 * 2);
-                console::log_1(&format!("{}Node {}: {:?}", pad, node.index(), node.name()).into());
+                logger::log(&format!("{}Node {}: {:?}", pad, node.index(), node.name()));
 
                 if let Some(mesh) = node.mesh() {
-                    console::log_1(&format!("{}  Mesh: {:?}", pad, mesh.name()).into());
+                    logger::log(&format!("{}  Mesh: {:?}", pad, mesh.name()));
                 }
                 if let Some(camera) = node.camera() {
-                    console::log_1(&format!("{}  Camera: {:?}", pad, camera.name()).into());
+                    logger::log(&format!("{}  Camera: {:?}", pad, camera.name()));
                 }
                 if let Some(skin) = node.skin() {
-                    console::log_1(&format!("{}  Skin: {:?}", pad, skin.name()).into());
+                    logger::log(&format!("{}  Skin: {:?}", pad, skin.name()));
                 }
             }
         }If the texture is embedd
         */
         // Materials
-        console::log_1(&format!("Materials: {}", model.materials().count()).into());
+        logger::log(&format!("Materials: {}", model.materials().count()));
         /* for (i, mat) in model.materials().enumerate() {
-            console::log_1(&format!("  Material {}: {:?}", i, mat.name()).into());
+            logger::log(&format!("  Material {}: {:?}", i, mat.name()));
         } */
 
         // Textures
-        console::log_1(&format!("Textures: {}", model.textures().count()).into());
+        logger::log(&format!("Textures: {}", model.textures().count()));
         /* for (i, tex) in model.textures().enumerate() {
-            console::log_1(&format!("  Texture {}: source = {:?}", i, tex.source().name()).into());
+            logger::log(&format!("  Texture {}: source = {:?}", i, tex.source().name()));
         } */
 
         // Images
-        console::log_1(&format!("Images: {}", model.images().count()).into());
+        logger::log(&format!("Images: {}", model.images().count()));
         /* for (i, img) in model.images().enumerate() {
-            console::log_1(&format!("  Image {}: {:?}", i, img.name()).into());
+            logger::log(&format!("  Image {}: {:?}", i, img.name()));
         } */
 
         // Samplers
-        console::log_1(&format!("Samplers: {}", model.samplers().count()).into());
+        logger::log(&format!("Samplers: {}", model.samplers().count()));
         /* for (i, samp) in model.samplers().enumerate() {
-            console::log_1(
-                &format!(
+            logger::log(&format!(
                     "  Sampler {}: mag={:?}, min={:?}, wrap_s={:?}, wrap_t={:?}",
                     i,
                     samp.mag_filter(),
                     samp.min_filter(),
                     samp.wrap_s(),
                     samp.wrap_t()
-                )
-                .into(),
-            );
+                ));
         } */
 
         // Buffers
-        console::log_1(&format!("Buffers: {}", model.buffers().count()).into());
+        logger::log(&format!("Buffers: {}", model.buffers().count()));
         /* for (i, buf) in model.buffers().enumerate() {
-            console::log_1(&format!("  Buffer {}: byte_length={}", i, buf.length()).into());
+            logger::log(&format!("  Buffer {}: byte_length={}", i, buf.length()));
         } */
 
         // Accessors
-        console::log_1(&format!("Accessors: {}", model.accessors().count()).into());
+        logger::log(&format!("Accessors: {}", model.accessors().count()));
         /* for (i, accessor) in model.accessors().enumerate() {
-            console::log_1(
-                &format!(
+            logger::log(&format!(
                     "  Accessor {}: type={:?}, count={}, component_type={:?}, view={:?}",
                     i,
                     accessor.dimensions(),
                     accessor.count(),
                     accessor.data_type(),
                     accessor.view().map(|v| v.index())
-                )
-                .into(),
-            );
+                ));
         } */
 
         // Animations
-        console::log_1(&format!("Animations: {}", model.animations().count()).into());
+        logger::log(&format!("Animations: {}", model.animations().count()));
         /* for (i, anim) in model.animations().enumerate() {
-            console::log_1(&format!("  Animation {}: {:?}", i, anim.name()).into());
+            logger::log(&format!("  Animation {}: {:?}", i, anim.name()));
             for channel in anim.channels() {
                 let target = channel.target();
-                console::log_1(&format!("    Channel: node={:?}", target.node().index()).into());
+                logger::log(&format!("    Channel: node={:?}", target.node().index()));
             }
         } */
 
         // Skins
-        console::log_1(&format!("Skins: {}", model.skins().count()).into());
+        logger::log(&format!("Skins: {}", model.skins().count()));
         /* for (i, skin) in model.skins().enumerate() {
-            console::log_1(
-                &format!(
+            logger::log(&format!(
                     "  Skin {}: {:?}, joints={}",
                     i,
                     skin.name(),
                     skin.joints().count()
-                )
-                .into(),
-            );
+                ));
         } */
-        console::log_1(&"===================================".into());
+        logger::log("===================================");
     }
 }
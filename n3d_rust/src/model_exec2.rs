@@ -1,4 +1,5 @@
 use super::custom_structs::CustomStructs;
+use crate::logger;
 use glam::{Mat4, Vec3};
 use gltf::buffer::Data;
 use gltf::image::Format;
@@ -9,7 +10,6 @@ use gltf_json::material;
 use std::num::NonZeroU32;
 use std::primitive;
 use wasm_bindgen::JsValue;
-use web_sys::console;
 use wgpu::util::DeviceExt;
 use wgpu::BindGroup;
 
@@ -0,0 +1,122 @@
+use crate::renderer_error::RendererError;
+use wgpu::util::DeviceExt;
+
+// Targets blended per draw, the most influential ones when a mesh has more. Their weights and
+// indices ride along in CameraUniform.
+pub const MAX_ACTIVE_TARGETS: usize = 8;
+
+// WebGL2-level devices have no storage buffers, the deltas of every primitive are packed into
+// one Rgba32Float texture read with textureLoad in the vertex stage. A primitive's deltas are
+// target-major: texel `base + (target * vertex_count + vertex) * 3 + attribute` with position,
+// normal and tangent as attributes 0, 1 and 2, as morph_texel in the vertex and shadow shaders
+// expects.
+#[derive(Default)]
+pub struct MorphDeltas {
+    texels: Vec<[f32; 4]>,
+}
+
+// POSITION, NORMAL and TANGENT deltas of one target, empty when the target leaves it out
+pub struct TargetDeltas {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 3]>,
+}
+
+impl MorphDeltas {
+    // Appends a primitive's targets and returns the texel its deltas start at
+    pub fn push(&mut self, targets: &[TargetDeltas], vertex_count: usize) -> u32 {
+        let base = self.texels.len() as u32;
+        for target in targets {
+            for vertex in 0..vertex_count {
+                for attribute in [&target.positions, &target.normals, &target.tangents] {
+                    let [x, y, z] = attribute.get(vertex).copied().unwrap_or([0.0; 3]);
+                    self.texels.push([x, y, z, 0.0]);
+                }
+            }
+        }
+        base
+    }
+
+    // Rows of the device's widest 2D texture; a single zero texel when no primitive has targets
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<wgpu::TextureView, RendererError> {
+        let max_size = device.limits().max_texture_dimension_2d;
+        let texel_count = self.texels.len().max(1) as u32;
+        let width = texel_count.min(max_size);
+        let height = texel_count.div_ceil(width);
+        RendererError::check_limit("morph target texture rows", height as u64, max_size as u64)?;
+        let mut texels = self.texels.clone();
+        texels.resize((width * height) as usize, [0.0; 4]);
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Morph Target Deltas"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&texels),
+        );
+        Ok(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+}
+
+// The up to MAX_ACTIVE_TARGETS largest of `weights` (zeros left out) as packed for
+// CameraUniform: weights, target indices and how many are set
+pub fn active_targets(weights: &[f32], target_count: usize) -> ([[f32; 4]; 2], [[u32; 4]; 2], u32) {
+    let mut active: Vec<(usize, f32)> = weights
+        .iter()
+        .copied()
+        .enumerate()
+        .take(target_count)
+        .filter(|&(_, weight)| weight != 0.0)
+        .collect();
+    active.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+    active.truncate(MAX_ACTIVE_TARGETS);
+    let mut packed_weights = [[0.0; 4]; 2];
+    let mut packed_targets = [[0; 4]; 2];
+    for (slot, &(target, weight)) in active.iter().enumerate() {
+        packed_weights[slot / 4][slot % 4] = weight;
+        packed_targets[slot / 4][slot % 4] = target as u32;
+    }
+    (packed_weights, packed_targets, active.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn active_targets_keep_the_largest_weights() {
+        // 10 targets, two of them zero; the 8 largest by magnitude survive
+        let weights = [0.1, 0.0, -0.9, 0.3, 0.05, 0.7, 0.0, -0.2, 0.6, 0.4];
+        let (packed_weights, packed_targets, count) = active_targets(&weights, weights.len());
+        assert_eq!(count, 8);
+        assert_eq!(
+            packed_weights,
+            [[-0.9, 0.7, 0.6, 0.4], [0.3, -0.2, 0.1, 0.05]]
+        );
+        assert_eq!(packed_targets, [[2, 5, 8, 9], [3, 7, 0, 4]]);
+    }
+
+    #[test]
+    fn active_targets_drop_zeros_and_extra_weights() {
+        // weights past the mesh's target count are ignored
+        let (packed_weights, packed_targets, count) = active_targets(&[0.0, 0.5, 0.0, 1.0], 2);
+        assert_eq!(count, 1);
+        assert_eq!(packed_weights, [[0.5, 0.0, 0.0, 0.0], [0.0; 4]]);
+        assert_eq!(packed_targets, [[1, 0, 0, 0], [0; 4]]);
+    }
+}
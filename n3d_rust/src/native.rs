@@ -0,0 +1,153 @@
+use crate::logger;
+use crate::renderer_error::RendererError;
+use crate::renderer_options::RendererOptions;
+use crate::saved_state::SavedState;
+use crate::viewer::Viewer;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::time::Instant;
+use winit::application::ApplicationHandler;
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::window::{Window, WindowId};
+
+// Opens a window showing the model, lit by `environment` (a Radiance .hdr) if given,
+// and blocks until it is closed.
+// Left drag orbits, right/middle drag pans and the wheel dollies, like the web page.
+pub fn run(
+    model_bytes: Vec<u8>,
+    environment: Option<Vec<u8>>,
+    options: RendererOptions,
+) -> Result<(), RendererError> {
+    let event_loop = EventLoop::new().map_err(|e| RendererError::Window(e.to_string()))?;
+    let mut app = NativeApp {
+        model_bytes,
+        environment,
+        options,
+        window: None,
+        viewer: None,
+        error: None,
+        orbiting: false,
+        panning: false,
+        cursor: None,
+        last_frame: None,
+    };
+    event_loop
+        .run_app(&mut app)
+        .map_err(|e| RendererError::Window(e.to_string()))?;
+    match app.error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+struct NativeApp {
+    model_bytes: Vec<u8>,
+    environment: Option<Vec<u8>>,
+    options: RendererOptions,
+    window: Option<Arc<Window>>,
+    viewer: Option<Viewer>,
+    // first setup error, returned from run() once the loop exits
+    error: Option<RendererError>,
+    orbiting: bool,
+    panning: bool,
+    cursor: Option<PhysicalPosition<f64>>,
+    last_frame: Option<Instant>,
+}
+
+impl NativeApp {
+    fn create_viewer(&self, window: Arc<Window>) -> Result<Viewer, RendererError> {
+        pollster::block_on(async {
+            let saved_gpu = SavedState::from_window(window, &self.options).await?;
+            let viewer = Viewer::new(saved_gpu, &self.options).await?;
+            if let Some(environment) = &self.environment {
+                viewer.load_environment(environment)?;
+            }
+            viewer.load_model(&self.model_bytes, None).await?;
+            // the first clip loops, if the model has any
+            if !viewer.animations().is_empty() {
+                viewer.play_animation(0, 0.0)?;
+            }
+            Ok(viewer)
+        })
+    }
+}
+
+impl ApplicationHandler for NativeApp {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.viewer.is_some() {
+            return;
+        }
+        let attributes = Window::default_attributes().with_title("n3d viewer");
+        let window = match event_loop.create_window(attributes) {
+            Ok(window) => Arc::new(window),
+            Err(e) => {
+                self.error = Some(RendererError::Window(e.to_string()));
+                event_loop.exit();
+                return;
+            }
+        };
+        match self.create_viewer(window.clone()) {
+            Ok(viewer) => {
+                self.viewer = Some(viewer);
+                window.request_redraw();
+                self.window = Some(window);
+            }
+            Err(e) => {
+                self.error = Some(e);
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        let (Some(window), Some(viewer)) = (self.window.as_ref(), self.viewer.as_ref()) else {
+            return;
+        };
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::Resized(size) => viewer.resize(size.width, size.height),
+            WindowEvent::RedrawRequested => {
+                let now = Instant::now();
+                if let Some(last) = self.last_frame.replace(now) {
+                    viewer.advance_animation((now - last).as_secs_f32().min(0.25));
+                }
+                viewer.draw_frame();
+                window.request_redraw();
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => self.orbiting = pressed,
+                    MouseButton::Right | MouseButton::Middle => self.panning = pressed,
+                    _ => {}
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(last) = self.cursor {
+                    let size = window.inner_size();
+                    let dx = (position.x - last.x) as f32 / size.width.max(1) as f32;
+                    let dy = (position.y - last.y) as f32 / size.height.max(1) as f32;
+                    let mut camera = viewer.camera.borrow_mut();
+                    if self.orbiting {
+                        camera.orbit(dx * PI, dy * PI);
+                    } else if self.panning {
+                        camera.pan(dx * 2.0, -dy * 2.0);
+                    }
+                }
+                self.cursor = Some(position);
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                // one wheel notch is ~100 pixels, same scale as the web page's deltaY * 0.001
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / 100.0,
+                };
+                viewer.camera.borrow_mut().dolly(-lines * 0.1);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::background::BackgroundMode;
+use crate::lights::Light;
+use crate::logger;
+use crate::renderer_error::RendererError;
+use crate::renderer_options::RendererOptions;
+use crate::saved_state::SavedState;
+use crate::shadows::ShadowSettings;
+use crate::tone_mapping::ToneMapping;
+use crate::viewer::Viewer;
+use std::cell::RefCell;
+
+// Color format of headless frames; sRGB like the swapchain formats SavedState prefers
+pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Tightly packed 8-bit RGBA pixels with straight alpha, rows top to bottom
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl RenderedImage {
+    pub fn to_png(&self) -> Result<Vec<u8>, RendererError> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| RendererError::Readback(e.to_string()))?;
+        writer
+            .write_image_data(&self.rgba)
+            .map_err(|e| RendererError::Readback(e.to_string()))?;
+        writer
+            .finish()
+            .map_err(|e| RendererError::Readback(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    // Any 8-bit PNG; grayscale, RGB and palette images are expanded to RGBA
+    pub fn from_png(bytes: &[u8]) -> Result<RenderedImage, RendererError> {
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| RendererError::Readback(e.to_string()))?;
+        let mut buf = vec![0; reader.output_buffer_size().unwrap_or(0)];
+        let info = reader
+            .next_frame(&mut buf)
+            .map_err(|e| RendererError::Readback(e.to_string()))?;
+        let pixels = &buf[..info.buffer_size()];
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => pixels.to_vec(),
+            png::ColorType::Rgb => pixels
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => pixels
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => pixels.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+            png::ColorType::Indexed => {
+                return Err(RendererError::Readback(
+                    "indexed PNG was not expanded".to_string(),
+                ))
+            }
+        };
+        Ok(RenderedImage {
+            width: info.width,
+            height: info.height,
+            rgba,
+        })
+    }
+}
+
+// Color texture that frames are rendered into instead of a swapchain image
+pub struct OffscreenTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OffscreenTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> OffscreenTarget {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Color Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        OffscreenTarget {
+            texture,
+            view,
+            width,
+            height,
+        }
+    }
+
+    // Copies the texture into a mappable buffer and blocks until the GPU is done
+    pub fn read_rgba(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<RenderedImage, RendererError> {
+        let unpadded_row = self.width * 4;
+        let padded_row = unpadded_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback Buffer"),
+            size: padded_row as u64 * self.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            self.texture.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device
+            .poll(wgpu::PollType::Wait)
+            .map_err(|e| RendererError::Readback(e.to_string()))?;
+        receiver
+            .recv()
+            .map_err(|e| RendererError::Readback(e.to_string()))?
+            .map_err(|e| RendererError::Readback(e.to_string()))?;
+
+        let mut rgba = Vec::with_capacity((unpadded_row * self.height) as usize);
+        {
+            let mapped = slice.get_mapped_range();
+            for row in mapped.chunks_exact(padded_row as usize) {
+                rgba.extend_from_slice(&row[..unpadded_row as usize]);
+            }
+        }
+        buffer.unmap();
+        if matches!(
+            self.texture.format(),
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            for pixel in rgba.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        // frames are premultiplied (see BackgroundMode::Transparent), PNGs expect straight alpha
+        for pixel in rgba.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            if alpha != 0 && alpha != 255 {
+                for channel in &mut pixel[..3] {
+                    *channel = (*channel as u32 * 255 / alpha).min(255) as u8;
+                }
+            }
+        }
+        Ok(RenderedImage {
+            width: self.width,
+            height: self.height,
+            rgba,
+        })
+    }
+}
+
+// Renders models without a window, e.g. for thumbnails or reference-image tests.
+// `force_fallback_adapter` in the options selects a software rasterizer.
+pub struct HeadlessRenderer {
+    viewer: Viewer,
+    target: RefCell<OffscreenTarget>,
+}
+
+impl HeadlessRenderer {
+    pub async fn new(
+        width: u32,
+        height: u32,
+        options: &RendererOptions,
+    ) -> Result<HeadlessRenderer, RendererError> {
+        let saved_gpu = SavedState::headless(width, height, OFFSCREEN_FORMAT, options).await?;
+        let (width, height) = saved_gpu.get_surface_size();
+        let target = OffscreenTarget::new(&saved_gpu.get_device(), width, height, OFFSCREEN_FORMAT);
+        Ok(HeadlessRenderer {
+            viewer: Viewer::new(saved_gpu, options).await?,
+            target: RefCell::new(target),
+        })
+    }
+
+    pub async fn load_model(
+        &self,
+        bytes: &[u8],
+        scene: Option<usize>,
+    ) -> Result<(), RendererError> {
+        self.viewer.load_model(bytes, scene).await
+    }
+
+    pub fn load_environment(&self, hdr_bytes: &[u8]) -> Result<(), RendererError> {
+        self.viewer.load_environment(hdr_bytes)
+    }
+
+    pub fn set_environment_intensity(&self, intensity: f32) {
+        self.viewer.set_environment_intensity(intensity);
+    }
+
+    // Transparent backgrounds come back with alpha 0 around the model
+    pub fn set_background(&self, mode: BackgroundMode) -> Result<(), RendererError> {
+        self.viewer.set_background(mode)
+    }
+
+    pub fn load_background_image(&self, bytes: &[u8]) -> Result<(), RendererError> {
+        self.viewer.load_background_image(bytes)
+    }
+
+    // World space light on top of the model's KHR_lights_punctual lights
+    pub fn add_light(&self, light: Light) -> usize {
+        self.viewer.add_light(light)
+    }
+
+    pub fn clear_lights(&self) {
+        self.viewer.clear_lights();
+    }
+
+    pub fn set_shadows(&self, settings: ShadowSettings) {
+        self.viewer.set_shadows(settings);
+    }
+
+    pub fn set_tone_mapping(&self, mapping: ToneMapping) {
+        self.viewer.set_tone_mapping(mapping);
+    }
+
+    pub fn set_exposure(&self, exposure: f32) {
+        self.viewer.set_exposure(exposure);
+    }
+
+    // Adapter that was picked, e.g. to tell a software rasterizer from a GPU
+    pub fn adapter_info(&self) -> wgpu::AdapterInfo {
+        self.viewer.saved_gpu.get_adapter().get_info()
+    }
+
+    // Draws the loaded model with the current camera and reads the frame back
+    pub fn render(&self) -> Result<RenderedImage, RendererError> {
+        let target = self.target.borrow();
+        if !self.viewer.draw_to_view(&target.view) {
+            return Err(RendererError::Readback("no model loaded".to_string()));
+        }
+        let image = target.read_rgba(
+            &self.viewer.saved_gpu.get_device(),
+            &self.viewer.saved_gpu.get_queue(),
+        )?;
+        logger::log(&format!(
+            "rendered {}x{} offscreen",
+            image.width, image.height
+        ));
+        Ok(image)
+    }
+
+    pub fn resize(&self, width: u32, height: u32) {
+        self.viewer.resize(width, height);
+        let (width, height) = self.viewer.saved_gpu.get_surface_size();
+        *self.target.borrow_mut() = OffscreenTarget::new(
+            &self.viewer.saved_gpu.get_device(),
+            width,
+            height,
+            OFFSCREEN_FORMAT,
+        );
+    }
+
+    // Names and durations of the model's clips, see Viewer::animations
+    pub fn animations(&self) -> Vec<(Option<String>, f32)> {
+        self.viewer.animations()
+    }
+
+    pub fn animation_index(&self, name: &str) -> Option<usize> {
+        self.viewer.animation_index(name)
+    }
+
+    // Headless frames don't advance on their own: play, then advance_animation or seek
+    pub fn play_animation(&self, index: usize, fade: f32) -> Result<(), RendererError> {
+        self.viewer.play_animation(index, fade)
+    }
+
+    pub fn pause_animation(&self) {
+        self.viewer.pause_animation();
+    }
+
+    pub fn set_animation_loop(&self, looping: bool) {
+        self.viewer.set_animation_loop(looping);
+    }
+
+    pub fn set_animation_speed(&self, speed: f32) {
+        self.viewer.set_animation_speed(speed);
+    }
+
+    pub fn seek_animation(&self, time: f32) {
+        self.viewer.seek_animation(time);
+    }
+
+    pub fn advance_animation(&self, delta: f32) {
+        self.viewer.advance_animation(delta);
+    }
+
+    // Names and morph target counts of the model's meshes, see Viewer::meshes
+    pub fn meshes(&self) -> Vec<(Option<String>, usize)> {
+        self.viewer.meshes()
+    }
+
+    pub fn set_morph_weights(&self, mesh: usize, weights: &[f32]) -> Result<(), RendererError> {
+        self.viewer.set_morph_weights(mesh, weights)
+    }
+
+    // Camera controls, same units as the wasm Renderer's rotate / zoom / pan
+    pub fn rotate(&self, delta_yaw: f32, delta_pitch: f32) {
+        self.viewer
+            .camera
+            .borrow_mut()
+            .orbit(delta_yaw, delta_pitch);
+    }
+    pub fn zoom(&self, delta: f32) {
+        self.viewer.camera.borrow_mut().dolly(delta);
+    }
+    pub fn pan(&self, delta_x: f32, delta_y: f32) {
+        self.viewer.camera.borrow_mut().pan(delta_x, delta_y);
+    }
+}
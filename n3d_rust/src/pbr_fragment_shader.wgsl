@@ -3,6 +3,8 @@ struct FSInput {
     @location(1) frag_uv   : vec2<f32>,   // texture coordinates
     @location(2) frag_norm : vec3<f32>,   // normal in world space
     @location(3) frag_col  : vec4<f32>,   // vertex color
+    @location(4) frag_tangent : vec4<f32>, // world space tangent, w = bitangent sign
+    @builtin(front_facing) front_facing : bool,
 };
 
 // === Camera + model uniform (from vertex shader UBO) ===
@@ -10,17 +12,34 @@ struct Uniforms {
     viewProj   : mat4x4<f32>,
     model      : mat4x4<f32>,
     cameraPos  : vec3<f32>,
-    _pad       : f32, // pad to 16 bytes
+    skinned    : u32,  // this and the morph fields are only read by the vertex shader
+    morphWeights     : array<vec4<f32>, 2>,
+    morphIndices     : array<vec4<u32>, 2>,
+    morphBase        : u32,
+    morphVertexStart : u32,
+    morphVertexCount : u32,
+    morphCount       : u32,
 };
 @group(0) @binding(0)
 var<uniform> uniforms : Uniforms;
 
-// === Material uniforms (factors from glTF) ===
+// === Material uniforms (factors from glTF, laid out like MaterialPBR) ===
 struct Material {
-    baseColorFactor : vec4<f32>,
-    metallicFactor  : f32,
-    roughnessFactor : f32,
-    emissiveFactor  : vec3<f32>,
+    baseColorFactor          : vec4<f32>,
+    emissiveFactor           : vec3<f32>,
+    metallicFactor           : f32,
+    roughnessFactor          : f32,
+    // glTF texture indices, only used on the CPU
+    baseColorTexture         : u32,
+    metallicRoughnessTexture : u32,
+    normalTexture            : u32,
+    occlusionTexture         : u32,
+    emissiveTexture          : u32,
+    alphaCutoff              : f32,
+    alphaMode                : u32,   // 0 opaque, 1 mask, 2 blend
+    doubleSided              : u32,
+    normalScale              : f32,
+    _pad                     : vec2<u32>,
 };
 @group(1) @binding(0)
 var<uniform> material : Material;
@@ -37,79 +56,210 @@ var<uniform> material : Material;
 @group(1) @binding(9) var emissiveTex : texture_2d<f32>;
 @group(1) @binding(10) var emissiveSampler : sampler;
 
-// === Directional light ===
-struct Light {
-    direction : vec3<f32>,
-    color     : vec3<f32>,
+// === Punctual lights (KHR_lights_punctual, packed in lights.rs) ===
+const MAX_LIGHTS : u32 = 16u;
+struct PunctualLight {
+    position    : vec3<f32>,
+    range       : f32,        // 0 = no cutoff
+    direction   : vec3<f32>,  // where the light shines
+    kind        : u32,        // 0 directional, 1 point, 2 spot
+    color       : vec3<f32>,
+    intensity   : f32,        // candela (point, spot) or lux (directional)
+    angleScale  : f32,        // spot cone: 1 / (cos inner - cos outer)
+    angleOffset : f32,        // -cos outer * angleScale
+    _pad        : vec2<f32>,
+};
+struct Lights {
+    lights : array<PunctualLight, MAX_LIGHTS>,
+    count  : u32,
 };
 @group(2) @binding(0)
-var<uniform> light : Light;
+var<uniform> lights : Lights;
 
-@fragment
-fn fs_main(input : FSInput) -> @location(0) vec4<f32> {
-    // --- 1. Base color ---
-    var baseColor = material.baseColorFactor;
-    baseColor = textureSample(baseColorTex, baseColorSampler, input.frag_uv);
-    if (any(input.frag_col.rgb != vec3<f32>(0.0))) {
-        baseColor *= input.frag_col;
-    }
+// === Key light shadow map (rendered in shadows.rs) ===
+struct Shadow {
+    lightViewProj : mat4x4<f32>,
+    toLight       : vec3<f32>,
+    bias          : f32,        // depth bias in light clip space
+    normalBias    : f32,        // world units along the normal
+    pcfRadius     : i32,        // texels
+    enabled       : u32,
+    texelSize     : f32,
+    strength      : f32,        // environment light removed in shadow
+    keyLight      : i32,        // shadowed slot in `lights`, -1 = the environment
+    _pad          : vec2<f32>,
+};
+@group(2) @binding(1) var<uniform> shadow : Shadow;
+@group(2) @binding(2) var shadowMap : texture_depth_2d;
+@group(2) @binding(3) var shadowSampler : sampler_comparison;
 
-    // --- 2. Metallic + Roughness ---
-    let mrSample = textureSample(metallicRoughnessTex, metallicRoughnessSampler, input.frag_uv);
-    let metallic  = material.metallicFactor * mrSample.b;
-    let roughness = material.roughnessFactor * mrSample.g;
+// === Image based lighting (baked in ibl.rs) ===
+struct Environment {
+    intensity : f32,
+    maxLod    : f32,   // prefiltered mip for roughness 1
+    _pad      : vec2<f32>,
+};
+@group(3) @binding(0) var<uniform> environment : Environment;
+@group(3) @binding(1) var irradianceMap : texture_cube<f32>;
+@group(3) @binding(2) var prefilteredMap : texture_cube<f32>;
+@group(3) @binding(3) var brdfLut : texture_2d<f32>;
+@group(3) @binding(4) var iblSampler : sampler;
 
-    // --- 3. Normal mapping ---
-    var N = normalize(input.frag_norm);
-    let normalSample = textureSample(normalTex, normalSampler, input.frag_uv).rgb * 2.0 - 1.0;
-    if (length(normalSample) > 0.01) {
-        N = normalize(normalSample);
+// Inverse square falloff, smoothly cut off at `range` when one is given
+fn range_attenuation(distance2 : f32, range : f32) -> f32 {
+    if (range <= 0.0) {
+        return 1.0 / distance2;
     }
+    let ratio2 = distance2 / (range * range);
+    return clamp(1.0 - ratio2 * ratio2, 0.0, 1.0) / distance2;
+}
 
-    // --- 4. View and light vectors (camera-aware) ---
-    let V = normalize(uniforms.cameraPos - input.frag_pos); // correct view vector
-    let L = normalize(-light.direction);                   // directional light
-    let H = normalize(V + L);
+// 1 = lit, 0 = fully shadowed; a (2 * pcfRadius + 1)^2 box of hardware filtered comparisons
+fn shadow_visibility(position : vec3<f32>, normal : vec3<f32>) -> f32 {
+    if (shadow.enabled == 0u) {
+        return 1.0;
+    }
+    let clip = shadow.lightViewProj * vec4<f32>(position + normal * shadow.normalBias, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+    var lit = 0.0;
+    var taps = 0.0;
+    for (var y = -shadow.pcfRadius; y <= shadow.pcfRadius; y++) {
+        for (var x = -shadow.pcfRadius; x <= shadow.pcfRadius; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texelSize;
+            lit += textureSampleCompareLevel(shadowMap, shadowSampler, uv + offset, ndc.z - shadow.bias);
+            taps += 1.0;
+        }
+    }
+    return lit / taps;
+}
 
+// Cook-Torrance GGX specular plus Lambert diffuse for one light, times N.L
+fn brdf_direct(N : vec3<f32>, V : vec3<f32>, L : vec3<f32>, albedo : vec3<f32>, F0 : vec3<f32>,
+               metallic : f32, roughness : f32) -> vec3<f32> {
+    let H = normalize(V + L);
     let NdotL = max(dot(N, L), 0.0);
     let NdotV = max(dot(N, V), 0.0);
     let NdotH = max(dot(N, H), 0.0);
     let VdotH = max(dot(V, H), 0.0);
 
-    // --- 5. Fresnel-Schlick approximation ---
-    let F0 = mix(vec3<f32>(0.04, 0.04, 0.04), baseColor.rgb, metallic);
+    // Fresnel-Schlick approximation
     let F = F0 + (1.0 - F0) * pow(1.0 - VdotH, 5.0);
 
-    // --- 6. Normal Distribution (GGX simplified) ---
+    // Normal distribution (GGX)
     let alpha = roughness * roughness;
     let denom = (NdotH * NdotH) * (alpha * alpha - 1.0) + 1.0;
     let D = alpha * alpha / (3.14159 * denom * denom);
 
-    // --- 7. Geometry term (Schlick-GGX) ---
+    // Geometry term (Schlick-GGX)
     let k = (alpha + 1.0) * (alpha + 1.0) / 8.0;
     let Gv = NdotV / (NdotV * (1.0 - k) + k);
     let Gl = NdotL / (NdotL * (1.0 - k) + k);
     let G = Gv * Gl;
 
-    // --- 8. Cook-Torrance BRDF ---
-    let numerator = D * G * F;
-    let specular = numerator / max(4.0 * NdotV * NdotL, 0.001);
+    let specular = D * G * F / max(4.0 * NdotV * NdotL, 0.001);
+    var kD = vec3<f32>(1.0) - F;      // energy not reflected is diffused
+    kD *= 1.0 - metallic;             // metals have no diffuse
+    let diffuse = kD * albedo / 3.14159;
+    return (diffuse + specular) * NdotL;
+}
+
+@fragment
+fn fs_main(input : FSInput) -> @location(0) vec4<f32> {
+    // --- 1. Base color ---
+    var baseColor = material.baseColorFactor * textureSample(baseColorTex, baseColorSampler, input.frag_uv);
+    if (any(input.frag_col.rgb != vec3<f32>(0.0))) {
+        baseColor *= input.frag_col;
+    }
+
+    // masked materials are either fully there or not at all
+    if (material.alphaMode == 1u) {
+        if (baseColor.a < material.alphaCutoff) {
+            discard;
+        }
+        baseColor.a = 1.0;
+    } else if (material.alphaMode == 0u) {
+        baseColor.a = 1.0;
+    }
+
+    // --- 2. Metallic + Roughness ---
+    let mrSample = textureSample(metallicRoughnessTex, metallicRoughnessSampler, input.frag_uv);
+    let metallic  = material.metallicFactor * mrSample.b;
+    let roughness = material.roughnessFactor * mrSample.g;
+
+    // --- 3. Normal mapping (tangent space, the default texture is flat) ---
+    // back faces of double-sided materials are lit from their own side
+    var geometricN = normalize(input.frag_norm);
+    if (!input.front_facing && material.doubleSided != 0u) {
+        geometricN = -geometricN;
+    }
+    var N = geometricN;
+    var normalSample = textureSample(normalTex, normalSampler, input.frag_uv).rgb * 2.0 - 1.0;
+    normalSample = vec3<f32>(normalSample.xy * material.normalScale, normalSample.z);
+    let tangent = input.frag_tangent.xyz - N * dot(N, input.frag_tangent.xyz);
+    if (dot(tangent, tangent) > 1e-8) {
+        let T = normalize(tangent);
+        let B = cross(N, T) * input.frag_tangent.w;
+        N = normalize(mat3x3<f32>(T, B, N) * normalSample);
+    }
 
-    let kS = F;                       // specular reflection
-    var kD = vec3<f32>(1.0) - kS;     // diffuse reflection
-    kD *= 1.0 - metallic;             // metals have less diffuse
-    let diffuse = kD * baseColor.rgb / 3.14159;
+    // --- 4. View vector and reflectance at normal incidence ---
+    let V = normalize(uniforms.cameraPos - input.frag_pos);
+    let NdotV = max(dot(N, V), 0.0);
+    let F0 = mix(vec3<f32>(0.04, 0.04, 0.04), baseColor.rgb, metallic);
 
-    var finalColor = (diffuse + specular) * light.color * NdotL;
-    
+    // --- 5. Punctual lights, the key light shadowed ---
+    let visibility = shadow_visibility(input.frag_pos, geometricN);
+    var finalColor = vec3<f32>(0.0);
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i++) {
+        let light = lights.lights[i];
+        var L = -light.direction;
+        var attenuation = 1.0;
+        if (light.kind != 0u) {
+            let toLight = light.position - input.frag_pos;
+            let distance2 = max(dot(toLight, toLight), 0.0001);
+            L = toLight * inverseSqrt(distance2);
+            attenuation = range_attenuation(distance2, light.range);
+            if (light.kind == 2u) {
+                let cd = dot(light.direction, -L);
+                let spot = clamp(cd * light.angleScale + light.angleOffset, 0.0, 1.0);
+                attenuation *= spot * spot;
+            }
+        }
+        var radiance = light.color * light.intensity * attenuation;
+        if (i32(i) == shadow.keyLight) {
+            radiance *= visibility;
+        }
+        finalColor += brdf_direct(N, V, normalize(L), baseColor.rgb, F0, metallic, roughness) * radiance;
+    }
+
+    // --- 6. Image based lighting (split sum) ---
+    let R = reflect(-V, N);
+    let F_ibl = F0 + (max(vec3<f32>(1.0 - roughness), F0) - F0) * pow(1.0 - NdotV, 5.0);
+    let kD_ibl = (vec3<f32>(1.0) - F_ibl) * (1.0 - metallic);
+    let irradiance = textureSample(irradianceMap, iblSampler, N).rgb;
+    let prefiltered = textureSampleLevel(prefilteredMap, iblSampler, R, roughness * environment.maxLod).rgb;
+    let envBrdf = textureSample(brdfLut, iblSampler, vec2<f32>(NdotV, roughness)).rg;
+    var ambient = (kD_ibl * irradiance * baseColor.rgb + prefiltered * (F_ibl * envBrdf.x + envBrdf.y))
+        * environment.intensity;
+    // without a directional light the environment's brightest direction casts the shadow;
+    // surfaces turned away from it are already dim and keep their light
+    if (shadow.keyLight < 0) {
+        let facing = clamp(dot(geometricN, shadow.toLight) * 2.0, 0.0, 1.0);
+        ambient *= mix(1.0, visibility, shadow.strength * facing);
+    }
 
-    // --- 9. Occlusion ---
+    // --- 7. Occlusion (indirect light only) ---
     let ao = textureSample(occlusionTex, occlusionSampler, input.frag_uv).r;
-    finalColor *= ao;
+    finalColor += ambient * ao;
 
-    // --- 10. Emissive ---
+    // --- 8. Emissive ---
     let emissive = material.emissiveFactor * textureSample(emissiveTex, emissiveSampler, input.frag_uv).rgb;
     finalColor += emissive;
 
-    return vec4<f32>(finalColor, baseColor.a);
+    // the HDR target holds premultiplied color
+    return vec4<f32>(finalColor * baseColor.a, baseColor.a);
 }
@@ -1,6 +1,9 @@
+use crate::background::Background;
 use crate::custom_structs::CustomStructs;
+use crate::logger;
+use crate::shadows::Shadows;
+use crate::tone_mapping::ToneMapper;
 use wasm_bindgen::JsValue;
-use web_sys::console;
 use winit::window::CustomCursor;
 
 pub struct RenderLoop {}
@@ -9,59 +12,77 @@ impl RenderLoop {
     pub async fn new() -> RenderLoop {
         Self {}
     }
-    pub async fn render2(
+    // Draws one frame of an already loaded model into the next swapchain texture
+    pub fn draw_frame(
         &self,
-        device: wgpu::Device,
-        queue: wgpu::Queue,
-        pipeline: wgpu::RenderPipeline,
-        model: CustomStructs::Model,
-        camera_bind_group: wgpu::BindGroup,
-        material_bind_group: wgpu::BindGroup,
-        texture_bind_group: wgpu::BindGroup,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
         surface: &wgpu::Surface<'static>,
-        depth_texture: &wgpu::TextureView,
-    ) -> JsValue {
-        console::log_1(&"renderloop starts".into());
-
+        resources: &CustomStructs::ModelResources,
+        draw_order: &[usize],
+        environment: &wgpu::BindGroup,
+        background: &Background,
+        shadows: &Shadows,
+        tone_mapper: &ToneMapper,
+    ) {
         //  Acquire next frame from swapchain
         let frame = match surface.get_current_texture() {
             Ok(frame) => frame,
             Err(e) => {
-                console::log_1(&format!("could not acquire next frame: {:?}", e).into());
-                return JsValue::NULL;
+                logger::log(&format!("could not acquire next frame: {:?}", e));
+                return;
             }
         };
 
         let frame_view = frame
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
-        console::log_1(&"frame view created".into());
+        self.draw_to_view(
+            device,
+            queue,
+            &frame_view,
+            resources,
+            draw_order,
+            environment,
+            background,
+            shadows,
+            tone_mapper,
+        );
+        frame.present();
+    }
 
+    // Records and submits one frame into `view`; the swapchain and offscreen targets both end here.
+    // The scene is drawn into the tone mapper's HDR target, which is then tone mapped over
+    // the background into `view`. `view` must have the surface format and the depth texture's size.
+    // `draw_order` lists the model's draws in recording order (Model::draw_order).
+    pub fn draw_to_view(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &wgpu::TextureView,
+        resources: &CustomStructs::ModelResources,
+        draw_order: &[usize],
+        environment: &wgpu::BindGroup,
+        background: &Background,
+        shadows: &Shadows,
+        tone_mapper: &ToneMapper,
+    ) {
         // Create command encoder
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
 
-        // Begin render pass with depth attachment
+        // Key light's depth first, the main pass samples it
+        let camera_stride = CustomStructs::CameraUniform::aligned_size(device);
+        shadows.draw_map(&mut encoder, resources, camera_stride);
+
+        // Begin the HDR pass with depth attachment; uncovered pixels stay transparent
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Main Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &frame_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.1,
-                            b: 0.1,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store, // keep results for presenting
-                    },
-                    depth_slice: None,
-                })],
+                color_attachments: &[Some(tone_mapper.scene_attachment())],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: depth_texture,
+                    view: &resources.depth_texture,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0), // far plane
                         store: wgpu::StoreOp::Store,
@@ -72,29 +93,102 @@ impl RenderLoop {
                 occlusion_query_set: None,
             });
 
-            //  Set pipeline and bind groups
-            render_pass.set_pipeline(&pipeline);
-            render_pass.set_bind_group(0, Some(&camera_bind_group), &[]);
-            render_pass.set_bind_group(1, Some(&material_bind_group), &[]);
-            render_pass.set_bind_group(2, Some(&texture_bind_group), &[]);
+            // skybox behind everything else
+            background.draw_hdr(&mut render_pass);
+
+            //  Bind groups shared by every draw
+            render_pass.set_bind_group(2, Some(&resources.light_bind_group), &[]);
+            render_pass.set_bind_group(3, Some(environment), &[]);
 
             // Set vertex/index buffers
+            let model = &resources.model;
             render_pass.set_vertex_buffer(0, model.vertex_buffer.slice(..));
             render_pass.set_index_buffer(model.indice_buffer.slice(..), wgpu::IndexFormat::Uint32);
 
-            //  Draw
-            render_pass.draw_indexed(0..model.indice_count, 0, 0..1);
+            //  Draw each node's primitives with its own material (last bind group is the default one)
+            //  and the pipeline for its alpha mode and culling
+            let default_material = resources.material_bind_groups.last();
+            let mut ground_drawn = false;
+            for &i in draw_order {
+                let primitive = &model.primitives[model.draws[i].primitive];
+                // shadow catcher on the floor, depth tested against the opaque and masked draws
+                // and blended over by the transparent ones
+                if primitive.alpha_mode == CustomStructs::ALPHA_BLEND && !ground_drawn {
+                    shadows.draw_ground(&mut render_pass);
+                    render_pass.set_bind_group(2, Some(&resources.light_bind_group), &[]);
+                    render_pass.set_bind_group(3, Some(environment), &[]);
+                    ground_drawn = true;
+                }
+                render_pass.set_pipeline(
+                    &resources.pipelines[CustomStructs::pipeline_index(
+                        primitive.alpha_mode,
+                        primitive.double_sided,
+                    )],
+                );
+                render_pass.set_bind_group(
+                    0,
+                    Some(&resources.camera_bind_group),
+                    &model.dynamic_offsets(i, camera_stride),
+                );
+                let material_bind_group = primitive
+                    .material_index
+                    .and_then(|m| resources.material_bind_groups.get(m))
+                    .or(default_material);
+                render_pass.set_bind_group(1, material_bind_group, &[]);
+                render_pass.draw_indexed(
+                    primitive.first_index..primitive.first_index + primitive.index_count,
+                    0,
+                    0..1,
+                );
+            }
+
+            if !ground_drawn {
+                shadows.draw_ground(&mut render_pass);
+            }
         } // render_pass ends here
 
+        // Display pass: background, then the tone mapped frame over it
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Tone Mapping Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(background.clear_color()),
+                        store: wgpu::StoreOp::Store, // keep results for presenting
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            background.draw(&mut render_pass);
+            tone_mapper.draw(&mut render_pass);
+        }
+
         // Submit commands
         queue.submit(Some(encoder.finish()));
-        frame.present();
+    }
 
-        JsValue::NULL
+    // Uploads the per-draw camera uniforms (see ModelExec::camera_uniform_bytes)
+    pub fn update_camera(
+        &self,
+        queue: &wgpu::Queue,
+        camera_buffer: &wgpu::Buffer,
+        camera_uniforms: &[u8],
+    ) {
+        queue.write_buffer(camera_buffer, 0, camera_uniforms);
     }
 
-    pub async fn rotate() {
-       // queue.write_buffer(&uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    pub fn update_lights(
+        &self,
+        queue: &wgpu::Queue,
+        light_buffer: &wgpu::Buffer,
+        lights: &CustomStructs::LightsUniform,
+    ) {
+        queue.write_buffer(light_buffer, 0, bytemuck::bytes_of(lights));
     }
     pub async fn render(
         &self,
@@ -108,15 +202,15 @@ impl RenderLoop {
         surface: &wgpu::Surface<'static>,
         depth_texture: &wgpu::TextureView,
     ) -> JsValue {
-        console::log_1(&"renderloop starts".into());
+        logger::log("renderloop starts");
         // 1. Acquire next texture from the swapchain
         let frame = surface.get_current_texture();
         match &frame {
             Ok(f) => {
-                console::log_1(&"Acquired next frame".into());
+                logger::log("Acquired next frame");
             }
             Err(e) => {
-                console::log_1(&format!("could not acquire next frame: {:?}", e).into());
+                logger::log(&format!("could not acquire next frame: {:?}", e));
             }
         }
         let view = frame
@@ -124,15 +218,15 @@ impl RenderLoop {
             .unwrap()
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
-        console::log_1(&"view created".into());
+        logger::log("view created");
 
         // 2. Create a command encoder
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
-        console::log_1(&"encoder created".into());
+        logger::log("encoder created");
 
-        console::log_1(&"render pass begins".into());
+        logger::log("render pass begins");
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -163,9 +257,9 @@ impl RenderLoop {
             render_pass.set_index_buffer(model.indice_buffer.slice(..), wgpu::IndexFormat::Uint32);
             render_pass.draw_indexed(0..model.indice_count, 0, 0..1);
 
-            console::log_1(&"render pass draws".into());
+            logger::log("render pass draws");
         } // <-- render_pass ends *here* when dropped
-        console::log_1(&"render pass ends".into());
+        logger::log("render pass ends");
 
         queue.submit(Some(encoder.finish()));
         frame.unwrap().present();
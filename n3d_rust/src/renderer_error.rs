@@ -0,0 +1,138 @@
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum RendererError {
+    // canvas element missing, not a <canvas>, or no window/document
+    NoCanvas(String),
+    // the canvas could not be turned into a wgpu surface
+    SurfaceCreation(String),
+    // native only: no event loop or window (e.g. no display server)
+    Window(String),
+    // no WebGPU / wgpu adapter compatible with the surface
+    NoAdapter(String),
+    DeviceRequest(String),
+    GltfParse(String),
+    // environment map is not a Radiance .hdr file
+    EnvironmentParse(String),
+    UnsupportedImageFormat {
+        image: usize,
+        format: String,
+    },
+    // headless frame could not be copied back or encoded
+    Readback(String),
+    // malformed value passed from JS or the command line, e.g. an unknown background mode
+    InvalidArgument(String),
+    // the surface or device can't do what was asked, e.g. composite with alpha
+    Unsupported(String),
+    // a resource needs more than the device allows
+    LimitExceeded {
+        limit: &'static str,
+        requested: u64,
+        allowed: u64,
+    },
+}
+
+impl RendererError {
+    // stable name for JS (`error.kind`)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RendererError::NoCanvas(_) => "NoCanvas",
+            RendererError::SurfaceCreation(_) => "SurfaceCreation",
+            RendererError::Window(_) => "Window",
+            RendererError::NoAdapter(_) => "NoAdapter",
+            RendererError::DeviceRequest(_) => "DeviceRequest",
+            RendererError::GltfParse(_) => "GltfParse",
+            RendererError::EnvironmentParse(_) => "EnvironmentParse",
+            RendererError::UnsupportedImageFormat { .. } => "UnsupportedImageFormat",
+            RendererError::Readback(_) => "Readback",
+            RendererError::InvalidArgument(_) => "InvalidArgument",
+            RendererError::Unsupported(_) => "Unsupported",
+            RendererError::LimitExceeded { .. } => "LimitExceeded",
+        }
+    }
+
+    // errors out when `requested` is above the device limit `allowed`
+    pub fn check_limit(limit: &'static str, requested: u64, allowed: u64) -> Result<(), Self> {
+        if requested > allowed {
+            return Err(RendererError::LimitExceeded {
+                limit,
+                requested,
+                allowed,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::NoCanvas(reason) => write!(f, "no usable canvas: {}", reason),
+            RendererError::SurfaceCreation(reason) => {
+                write!(f, "could not create a WebGPU surface: {}", reason)
+            }
+            RendererError::Window(reason) => write!(f, "could not open a window: {}", reason),
+            RendererError::NoAdapter(reason) => {
+                write!(f, "no compatible GPU adapter found: {}", reason)
+            }
+            RendererError::DeviceRequest(reason) => {
+                write!(f, "GPU device request failed: {}", reason)
+            }
+            RendererError::GltfParse(reason) => write!(f, "could not parse glTF: {}", reason),
+            RendererError::EnvironmentParse(reason) => {
+                write!(f, "could not decode HDR environment: {}", reason)
+            }
+            RendererError::UnsupportedImageFormat { image, format } => {
+                write!(f, "image {} has unsupported format {}", image, format)
+            }
+            RendererError::Readback(reason) => {
+                write!(f, "could not read back the frame: {}", reason)
+            }
+            RendererError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            RendererError::Unsupported(reason) => write!(f, "not supported: {}", reason),
+            RendererError::LimitExceeded {
+                limit,
+                requested,
+                allowed,
+            } => write!(
+                f,
+                "{} of {} exceeds the device limit of {}",
+                limit, requested, allowed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {}
+
+// Rejected promises carry a JS Error with `kind` plus the variant's fields
+#[cfg(target_arch = "wasm32")]
+impl From<RendererError> for wasm_bindgen::JsValue {
+    fn from(error: RendererError) -> Self {
+        use wasm_bindgen::JsValue;
+
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name("RendererError");
+        let mut fields: Vec<(&str, JsValue)> = vec![("kind", error.kind().into())];
+        match &error {
+            RendererError::UnsupportedImageFormat { image, format } => {
+                fields.push(("image", (*image as u32).into()));
+                fields.push(("format", format.as_str().into()));
+            }
+            RendererError::LimitExceeded {
+                limit,
+                requested,
+                allowed,
+            } => {
+                fields.push(("limit", (*limit).into()));
+                fields.push(("requested", (*requested as f64).into()));
+                fields.push(("allowed", (*allowed as f64).into()));
+            }
+            _ => {}
+        }
+        for (key, value) in fields {
+            let _ = js_sys::Reflect::set(&js_error, &key.into(), &value);
+        }
+        js_error.into()
+    }
+}
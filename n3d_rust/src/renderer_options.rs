@@ -0,0 +1,55 @@
+// Settings read from the options object passed to `new Renderer(canvas, options)`
+#[derive(Debug, Clone)]
+pub struct RendererOptions {
+    pub power_preference: wgpu::PowerPreference,
+    // follow the canvas' layout size with a ResizeObserver
+    pub auto_resize: bool,
+    // pick a software adapter (lavapipe / llvmpipe on native)
+    pub force_fallback_adapter: bool,
+    // MSAA samples per pixel, 1 (off) or 4; lowered to 1 when the device can't do 4
+    pub msaa_samples: u32,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        RendererOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            auto_resize: false,
+            force_fallback_adapter: false,
+            msaa_samples: 4,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl RendererOptions {
+    // Missing or malformed fields keep their defaults; `undefined` gives all defaults
+    pub fn from_js(options: &wasm_bindgen::JsValue) -> Self {
+        let mut parsed = RendererOptions::default();
+        if !options.is_object() {
+            return parsed;
+        }
+        let get = |key: &str| js_sys::Reflect::get(options, &key.into()).unwrap_or_default();
+        match get("powerPreference").as_string().as_deref() {
+            Some("low-power") => parsed.power_preference = wgpu::PowerPreference::LowPower,
+            Some("high-performance") => {
+                parsed.power_preference = wgpu::PowerPreference::HighPerformance
+            }
+            _ => {}
+        }
+        if let Some(auto_resize) = get("autoResize").as_bool() {
+            parsed.auto_resize = auto_resize;
+        }
+        if let Some(force_fallback) = get("forceFallbackAdapter").as_bool() {
+            parsed.force_fallback_adapter = force_fallback;
+        }
+        // msaa: 1 | 4, or true / false
+        let msaa = get("msaa");
+        if let Some(samples) = msaa.as_f64() {
+            parsed.msaa_samples = samples as u32;
+        } else if let Some(enabled) = msaa.as_bool() {
+            parsed.msaa_samples = if enabled { 4 } else { 1 };
+        }
+        parsed
+    }
+}
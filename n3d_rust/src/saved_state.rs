@@ -1,21 +1,32 @@
+use crate::logger;
+use crate::renderer_error::RendererError;
+use crate::renderer_options::RendererOptions;
+use std::cell::RefCell;
 use std::sync::Arc;
 use std::{ffi::c_void, ptr::NonNull};
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsCast;
-use web_sys::console;
+#[cfg(target_arch = "wasm32")]
 use web_sys::HtmlCanvasElement;
 use wgpu::SurfaceTarget;
 use wgpu::{Device, Features, Instance, Queue, Surface};
-#[cfg(target_arch = "wasm32")]
+#[cfg(not(target_arch = "wasm32"))]
+use winit::window::Window;
+
 pub struct SavedState {
     instance: Arc<wgpu::Instance>,
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    surface: wgpu::Surface<'static>,
-    surface_format: wgpu::TextureFormat,
+    // None when rendering headless; surface_config then only records format and size
+    surface: Option<wgpu::Surface<'static>>,
+    surface_config: RefCell<wgpu::SurfaceConfiguration>,
+    #[cfg(target_arch = "wasm32")]
+    canvas: HtmlCanvasElement,
+    #[cfg(not(target_arch = "wasm32"))]
+    window: Option<Arc<Window>>,
 }
 
-#[cfg(target_arch = "wasm32")]
 impl SavedState {
     pub fn get_instance(&self) -> Arc<wgpu::Instance> {
         self.instance.clone()
@@ -29,128 +40,289 @@ impl SavedState {
     pub fn get_queue(&self) -> wgpu::Queue {
         self.queue.clone()
     }
-    pub fn get_surface(&self) -> &wgpu::Surface<'static> {
-        &self.surface
+    pub fn get_surface(&self) -> Option<&wgpu::Surface<'static>> {
+        self.surface.as_ref()
     }
     pub fn get_surface_format(&self) -> wgpu::TextureFormat {
-        self.surface_format.clone()
+        self.surface_config.borrow().format.clone()
+    }
+    // What the device may do with `format`: the adapter's own capabilities when the
+    // adapter specific format feature is enabled, the WebGPU guarantees otherwise
+    pub fn format_features(&self, format: wgpu::TextureFormat) -> wgpu::TextureFormatFeatures {
+        let features = self.device.features();
+        if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            self.adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(features)
+        }
+    }
+    pub fn get_surface_size(&self) -> (u32, u32) {
+        let config = self.surface_config.borrow();
+        (config.width, config.height)
+    }
+    #[cfg(target_arch = "wasm32")]
+    pub fn get_canvas(&self) -> &HtmlCanvasElement {
+        &self.canvas
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn get_window(&self) -> Option<&Arc<Window>> {
+        self.window.as_ref()
     }
 }
 #[cfg(target_arch = "wasm32")]
 impl SavedState {
-    pub async fn new() -> Self {
+    pub async fn new(
+        canvas: HtmlCanvasElement,
+        options: &RendererOptions,
+    ) -> Result<Self, RendererError> {
         let instance = Arc::new(Self::create_instance().await);
-        let (surface, canvas) = Self::create_surface(&instance, String::from("canvas")).await;
-        let adapter = Self::create_adapter(&instance, &surface).await;
-        let (device, queue) = Self::create_device_and_queue(&adapter).await;
-        let surface_format = Self::config_surface(&device, &adapter, &surface, &canvas).await;
-        Self {
+        let surface = Self::create_surface(&instance, &canvas).await?;
+        let adapter = Self::create_adapter(&instance, Some(&surface), options).await?;
+        let (device, queue) = Self::create_device_and_queue(&adapter).await?;
+        let surface_config =
+            Self::config_surface(&device, &adapter, &surface, canvas.width(), canvas.height())
+                .await?;
+        Ok(Self {
             instance,
             adapter,
             device,
             queue,
-            surface,
-            surface_format,
-        }
+            surface: Some(surface),
+            surface_config: RefCell::new(surface_config),
+            canvas,
+        })
     }
 
-    async fn create_instance() -> wgpu::Instance {
-        let instance_descriptor = wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::BROWSER_WEBGPU,
-            flags: wgpu::InstanceFlags::empty(),
-            memory_budget_thresholds: wgpu::MemoryBudgetThresholds::default(),
-            backend_options: wgpu::BackendOptions::default(),
-        };
-        console::log_1(&"instance created!".into());
-        return wgpu::Instance::new(&instance_descriptor);
+    // New surface on `canvas` that reuses this state's instance, adapter, device and queue
+    pub async fn share(&self, canvas: HtmlCanvasElement) -> Result<Self, RendererError> {
+        let surface = Self::create_surface(&self.instance, &canvas).await?;
+        let surface_config = Self::config_surface(
+            &self.device,
+            &self.adapter,
+            &surface,
+            canvas.width(),
+            canvas.height(),
+        )
+        .await?;
+        Ok(Self {
+            instance: self.instance.clone(),
+            adapter: self.adapter.clone(),
+            device: self.device.clone(),
+            queue: self.queue.clone(),
+            surface: Some(surface),
+            surface_config: RefCell::new(surface_config),
+            canvas,
+        })
+    }
+
+    // Accepts either a canvas element or the id of one
+    pub fn resolve_canvas(
+        target: &wasm_bindgen::JsValue,
+    ) -> Result<HtmlCanvasElement, RendererError> {
+        if let Some(canvas_id) = target.as_string() {
+            let document = web_sys::window()
+                .and_then(|window| window.document())
+                .ok_or_else(|| RendererError::NoCanvas("no window/document".to_string()))?;
+            let element = document.get_element_by_id(&canvas_id).ok_or_else(|| {
+                RendererError::NoCanvas(format!("no element with id '{}'", canvas_id))
+            })?;
+            return element.dyn_into::<HtmlCanvasElement>().map_err(|_| {
+                RendererError::NoCanvas(format!("element '{}' is not a <canvas>", canvas_id))
+            });
+        }
+        target
+            .clone()
+            .dyn_into::<HtmlCanvasElement>()
+            .map_err(|_| RendererError::NoCanvas("expected a <canvas> or its id".to_string()))
     }
 
     async fn create_surface(
         instance: &Arc<wgpu::Instance>,
-        canvas_id: String,
-    ) -> (wgpu::Surface<'static>, HtmlCanvasElement) {
-        let window = web_sys::window().unwrap();
-        let document = window.document().unwrap();
-        let canvas = document.get_element_by_id(canvas_id.as_str()).unwrap();
-        let render_canvas = canvas.dyn_into::<HtmlCanvasElement>();
-        if render_canvas
-            .as_ref()
-            .unwrap()
-            .get_context("webgpu")
-            .is_err()
-        {
+        render_canvas: &HtmlCanvasElement,
+    ) -> Result<wgpu::Surface<'static>, RendererError> {
+        if render_canvas.get_context("webgpu").is_err() {
             // Canvas might already have a context
-            console::log_1(&"canvas already has context!".into());
+            logger::log("canvas already has context!");
         } else {
-            console::log_1(&"canvas has no context!".into());
+            logger::log("canvas has no context!");
         }
-        match &render_canvas {
-            Ok(r) => {
-                console::log_1(&"canvas created!".into());
-            }
-            Err(e) => {
-                console::log_1(&"canvas creation failed!".into());
-            }
+        let surface = instance
+            .create_surface(SurfaceTarget::Canvas(render_canvas.clone()))
+            .map_err(|e| RendererError::SurfaceCreation(e.to_string()))?;
+        logger::log("surface created!");
+        Ok(surface)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SavedState {
+    // Native surface on a winit window (Vulkan or GL, see create_instance)
+    pub async fn from_window(
+        window: Arc<Window>,
+        options: &RendererOptions,
+    ) -> Result<Self, RendererError> {
+        let instance = Arc::new(Self::create_instance().await);
+        let surface = instance
+            .create_surface(window.clone())
+            .map_err(|e| RendererError::SurfaceCreation(e.to_string()))?;
+        logger::log("surface created!");
+        let adapter = Self::create_adapter(&instance, Some(&surface), options).await?;
+        let (device, queue) = Self::create_device_and_queue(&adapter).await?;
+        let size = window.inner_size();
+        let surface_config = Self::config_surface(
+            &device,
+            &adapter,
+            &surface,
+            size.width.max(1),
+            size.height.max(1),
+        )
+        .await?;
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            surface: Some(surface),
+            surface_config: RefCell::new(surface_config),
+            window: Some(window),
+        })
+    }
+
+    // No window or surface: frames go to an offscreen texture of `format` (see offscreen.rs)
+    pub async fn headless(
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        options: &RendererOptions,
+    ) -> Result<Self, RendererError> {
+        let instance = Arc::new(Self::create_instance().await);
+        let adapter = Self::create_adapter(&instance, None, options).await?;
+        let (device, queue) = Self::create_device_and_queue(&adapter).await?;
+        let max_size = device.limits().max_texture_dimension_2d;
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: width.clamp(1, max_size),
+            height: height.clamp(1, max_size),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            surface: None,
+            surface_config: RefCell::new(surface_config),
+            window: None,
+        })
+    }
+}
+
+impl SavedState {
+    // Resizes the canvas backing store and reconfigures the surface to match.
+    // Returns false when the fitted size is unchanged and nothing was reconfigured.
+    pub fn resize_surface(&self, width: u32, height: u32) -> bool {
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        let (width, height) = fit_surface_size(width, height, max_size);
+        let mut config = self.surface_config.borrow_mut();
+        if config.width == width && config.height == height {
+            return false;
         }
-        if render_canvas
-            .as_ref()
-            .unwrap()
-            .get_context("webgpu")
-            .is_err()
+        #[cfg(target_arch = "wasm32")]
         {
-            // Canvas might already have a context
-            console::log_1(&"canvas already has context!".into());
-        } else {
-            console::log_1(&"canvas has no context!".into());
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
         }
-        // Canvas  not found error to be ignored
-        let surface =
-            instance.create_surface(SurfaceTarget::Canvas(render_canvas.clone().unwrap()));
-        match &surface {
-            Ok(s) => {
-                console::log_1(&"surface created!".into());
-            }
-            Err(e) => {
-                console::log_1(&format!("surface creation failed: {:?}", e).into());
+        config.width = width;
+        config.height = height;
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &config);
+        }
+        logger::log(&format!("surface resized to {}x{}", width, height));
+        true
+    }
+
+    // Switches between the opaque alpha mode config_surface picked and one that composites
+    // premultiplied alpha over the page. Returns false if the surface has no such mode;
+    // headless targets keep their alpha channel anyway.
+    pub fn set_transparent(&self, transparent: bool) -> bool {
+        let Some(surface) = &self.surface else {
+            return true;
+        };
+        let alpha_modes = surface.get_capabilities(&self.adapter).alpha_modes;
+        let alpha_mode = if transparent {
+            let composited = [
+                wgpu::CompositeAlphaMode::PreMultiplied,
+                wgpu::CompositeAlphaMode::Inherit,
+            ];
+            match composited.into_iter().find(|m| alpha_modes.contains(m)) {
+                Some(mode) => mode,
+                None => return false,
             }
+        } else if alpha_modes.contains(&wgpu::CompositeAlphaMode::Opaque) {
+            wgpu::CompositeAlphaMode::Opaque
+        } else {
+            alpha_modes[0]
+        };
+        let mut config = self.surface_config.borrow_mut();
+        if config.alpha_mode != alpha_mode {
+            config.alpha_mode = alpha_mode;
+            surface.configure(&self.device, &config);
+            logger::log(&format!("surface alpha mode set to {:?}", alpha_mode));
         }
-        (surface.unwrap(), render_canvas.unwrap())
+        true
+    }
+
+    async fn create_instance() -> wgpu::Instance {
+        // natively WGPU_BACKEND (e.g. "gl") overrides the backend choice
+        #[cfg(target_arch = "wasm32")]
+        let backends = wgpu::Backends::BROWSER_WEBGPU;
+        #[cfg(not(target_arch = "wasm32"))]
+        let backends =
+            wgpu::Backends::from_env().unwrap_or(wgpu::Backends::VULKAN | wgpu::Backends::GL);
+        let instance_descriptor = wgpu::InstanceDescriptor {
+            backends,
+            flags: wgpu::InstanceFlags::empty(),
+            memory_budget_thresholds: wgpu::MemoryBudgetThresholds::default(),
+            backend_options: wgpu::BackendOptions::default(),
+        };
+        logger::log("instance created!");
+        return wgpu::Instance::new(&instance_descriptor);
     }
 
     //#[cfg(target_arch = "wasm32")]
     async fn create_adapter<'a>(
         instance: &Arc<wgpu::Instance>,
-        surface: &'a wgpu::Surface<'a>,
-    ) -> wgpu::Adapter {
+        surface: Option<&'a wgpu::Surface<'a>>,
+        options: &RendererOptions,
+    ) -> Result<wgpu::Adapter, RendererError> {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
-                compatible_surface: Some(surface),
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
+                compatible_surface: surface,
+                power_preference: options.power_preference,
+                force_fallback_adapter: options.force_fallback_adapter,
             })
-            .await;
-        match &adapter {
-            Ok(a) => {
-                console::log_1(&"adapter created!".into());
-            }
-            Err(e) => {
-                console::log_1(&format!("adapter creation failed: {:?}", e).into());
-            }
-        }
-        adapter.unwrap()
+            .await
+            .map_err(|e| RendererError::NoAdapter(e.to_string()))?;
+        logger::log(&format!("adapter created! {:?}", adapter.get_info()));
+        Ok(adapter)
     }
 
-    async fn create_device_and_queue(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    async fn create_device_and_queue(
+        adapter: &wgpu::Adapter,
+    ) -> Result<(wgpu::Device, wgpu::Queue), RendererError> {
         let features = adapter.features();
-        println!("Supported adapter features:");
+        logger::log("Supported adapter features:");
         for feature in wgpu::Features::all().iter() {
             if features.contains(feature) {
-                console::log_1(&format!(" - {:?}", feature).into());
-                //println!("  - {:?}", feature);
+                logger::log(&format!(" - {:?}", feature));
             }
         }
 
-        let r_device = adapter
+        let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 required_features: adapter.features(),
                 required_limits: wgpu::Limits::downlevel_webgl2_defaults(),
@@ -158,63 +330,60 @@ impl SavedState {
                 memory_hints: wgpu::MemoryHints::Performance, // or MemoryUsage, or Manual { ... }
                 trace: wgpu::Trace::Off,
             })
-            .await;
-        match &r_device {
-            Ok(d) => {
-                console::log_1(&"device and queue created!".into());
-            }
-            Err(e) => {
-                console::log_1(&format!("device and queue creation failed: {:?}", e).into());
-            }
-        }
-        let d = r_device.unwrap();
-        (d.0, d.1)
+            .await
+            .map_err(|e| RendererError::DeviceRequest(e.to_string()))?;
+        logger::log("device and queue created!");
+        Ok((device, queue))
     }
 
     async fn config_surface<'a>(
         device: &wgpu::Device,
         adapter: &wgpu::Adapter,
         surface: &'a wgpu::Surface<'a>,
-        canvas: &HtmlCanvasElement,
-    ) -> wgpu::TextureFormat {
+        width: u32,
+        height: u32,
+    ) -> Result<wgpu::SurfaceConfiguration, RendererError> {
         let surface_caps = surface.get_capabilities(adapter);
-        console::log_1(&"Supported texture formats:".into());
+        logger::log("Supported texture formats:");
         for format in &surface_caps.formats {
-            console::log_1(&format!("{:?}", format).into());
+            logger::log(&format!("{:?}", format));
         }
-        console::log_1(&"Supported present_modes:".into());
+        logger::log("Supported present_modes:");
         for format in &surface_caps.present_modes {
-            console::log_1(&format!("{:?}", format).into());
+            logger::log(&format!("{:?}", format));
         }
-        console::log_1(&"Supported CompositeAlphaMode:".into());
+        logger::log("Supported CompositeAlphaMode:");
         for format in &surface_caps.alpha_modes {
-            console::log_1(&format!("{:?}", format).into());
+            logger::log(&format!("{:?}", format));
         }
-        console::log_1(&"Supported TextureUsages:".into());
+        logger::log("Supported TextureUsages:");
         for format in surface_caps.usages {
-            console::log_1(&format!("{:?}", format).into());
+            logger::log(&format!("{:?}", format));
         }
+        let Some(&first_format) = surface_caps.formats.first() else {
+            return Err(RendererError::SurfaceCreation(
+                "surface is not supported by the adapter".to_string(),
+            ));
+        };
         let surface_format = surface_caps
             .formats
             .iter()
             .copied()
             .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
-        surface.configure(
-            &device,
-            &wgpu::SurfaceConfiguration {
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                format: surface_format.clone(),
-                width: canvas.width(),
-                height: canvas.height(),
-                present_mode: surface_caps.present_modes[0],
-                alpha_mode: surface_caps.alpha_modes[0],
-                view_formats: vec![surface_format],
-                desired_maximum_frame_latency: 2,
-            },
-        );
-        console::log_1(&"surface configured!".into());
-        surface_format.clone()
+            .unwrap_or(first_format);
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format.clone(),
+            width,
+            height,
+            present_mode: surface_caps.present_modes[0],
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![surface_format],
+            desired_maximum_frame_latency: 2,
+        };
+        surface.configure(&device, &surface_config);
+        logger::log("surface configured!");
+        Ok(surface_config)
     }
 
     pub async fn create_shader_module(
@@ -226,22 +395,51 @@ impl SavedState {
                 label: Some("Vertex Shader"),
                 source: wgpu::ShaderSource::Wgsl(include_str!("vertex_shader.wgsl").into()),
             });
-        console::log_1(&"vert shader module loaded".into());
+        logger::log("vert shader module loaded");
         let frag = self
             .device
             .create_shader_module(wgpu::ShaderModuleDescriptor {
                 label: Some("Unlit Fragment Shader"),
                 source: wgpu::ShaderSource::Wgsl(include_str!("fragment_shader.wgsl").into()),
             });
-        console::log_1(&"fragment shader module loaded".into());
+        logger::log("fragment shader module loaded");
         let frag_pbr = self
             .device
             .create_shader_module(wgpu::ShaderModuleDescriptor {
                 label: Some("PBR Fragment Shader"),
                 source: wgpu::ShaderSource::Wgsl(include_str!("pbr_fragment_shader.wgsl").into()),
             });
-        console::log_1(&"PBR fragment shader module loaded".into());
-        console::log_1(&"shader modules configured!".into());
+        logger::log("PBR fragment shader module loaded");
+        logger::log("shader modules configured!");
         (vert, frag, frag_pbr)
     }
 }
+
+// Scales a requested surface size down uniformly until both sides fit `max_size`, so the
+// image keeps the layout's aspect ratio (WebGL2 devices allow only 2048 px per side)
+fn fit_surface_size(width: u32, height: u32, max_size: u32) -> (u32, u32) {
+    let scale = (max_size as f64 / width.max(1) as f64)
+        .min(max_size as f64 / height.max(1) as f64)
+        .min(1.0);
+    let fit = |side: u32| ((side as f64 * scale).round() as u32).clamp(1, max_size);
+    (fit(width), fit(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_surface_keeps_its_aspect_ratio() {
+        assert_eq!(fit_surface_size(4096, 1024, 2048), (2048, 512));
+        assert_eq!(fit_surface_size(1024, 4096, 2048), (512, 2048));
+    }
+
+    #[test]
+    fn surface_within_limits_is_unchanged() {
+        assert_eq!(fit_surface_size(1920, 1080, 2048), (1920, 1080));
+        assert_eq!(fit_surface_size(0, 0, 2048), (1, 1));
+        // a very thin surface still gets one pixel
+        assert_eq!(fit_surface_size(8192, 1, 2048), (2048, 1));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::renderer_error::RendererError;
+use glam::{Mat4, Quat, Vec3};
+
+#[derive(Debug, Clone)]
+pub struct SceneNode {
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    // index into the document's KHR_lights_punctual lights
+    pub light: Option<usize>,
+    // index into `document.skins()`, only meaningful together with `mesh`
+    pub skin: Option<usize>,
+    // local transform, matrices from the glTF are decomposed into TRS
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+    // morph target weights, the node's own or else its mesh's defaults
+    pub weights: Vec<f32>,
+}
+
+impl SceneNode {
+    pub fn local_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+// Node hierarchy of one glTF scene, indexed like `document.nodes()`
+#[derive(Debug, Clone)]
+pub struct SceneGraph {
+    pub nodes: Vec<SceneNode>,
+    pub roots: Vec<usize>,
+    pub world: Vec<Mat4>,
+}
+
+impl SceneGraph {
+    // Uses `scene_index`, which has to exist, else the document's default scene, else the
+    // first scene. Documents without scenes fall back to every node that has no parent.
+    pub fn from_document(
+        document: &gltf::Document,
+        scene_index: Option<usize>,
+    ) -> Result<SceneGraph, RendererError> {
+        let mut nodes: Vec<SceneNode> = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                SceneNode {
+                    parent: None,
+                    children: node.children().map(|child| child.index()).collect(),
+                    mesh: node.mesh().map(|mesh| mesh.index()),
+                    light: node.light().map(|light| light.index()),
+                    skin: node.skin().map(|skin| skin.index()),
+                    translation: Vec3::from_array(translation),
+                    rotation: Quat::from_array(rotation),
+                    scale: Vec3::from_array(scale),
+                    weights: node
+                        .weights()
+                        .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                        .map(|weights| weights.to_vec())
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+        for parent in 0..nodes.len() {
+            for child in nodes[parent].children.clone() {
+                nodes[child].parent = Some(parent);
+            }
+        }
+
+        let scene = match scene_index {
+            Some(i) => Some(document.scenes().nth(i).ok_or_else(|| {
+                RendererError::InvalidArgument(format!(
+                    "no scene {}, the asset has {}",
+                    i,
+                    document.scenes().count()
+                ))
+            })?),
+            None => document
+                .default_scene()
+                .or_else(|| document.scenes().next()),
+        };
+        let roots = match scene {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => (0..nodes.len())
+                .filter(|&i| nodes[i].parent.is_none())
+                .collect(),
+        };
+
+        let mut graph = SceneGraph {
+            world: vec![Mat4::IDENTITY; nodes.len()],
+            nodes,
+            roots,
+        };
+        graph.update_world_matrices();
+        Ok(graph)
+    }
+
+    // Recomputes every world matrix from the local transforms, parents first
+    pub fn update_world_matrices(&mut self) {
+        let mut stack: Vec<(usize, Mat4)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY))
+            .collect();
+        while let Some((node, parent_world)) = stack.pop() {
+            let world = parent_world * self.nodes[node].local_matrix();
+            self.world[node] = world;
+            for &child in &self.nodes[node].children {
+                stack.push((child, world));
+            }
+        }
+    }
+
+    // Every (node, mesh) pair reachable from the roots, in depth-first order
+    pub fn mesh_instances(&self) -> Vec<(usize, usize)> {
+        let mut instances = Vec::new();
+        let mut stack: Vec<usize> = self.roots.iter().rev().copied().collect();
+        while let Some(node) = stack.pop() {
+            if let Some(mesh) = self.nodes[node].mesh {
+                instances.push((node, mesh));
+            }
+            stack.extend(self.nodes[node].children.iter().rev());
+        }
+        instances
+    }
+
+    // Every (node, light) pair reachable from the roots, in depth-first order
+    pub fn light_instances(&self) -> Vec<(usize, usize)> {
+        let mut instances = Vec::new();
+        let mut stack: Vec<usize> = self.roots.iter().rev().copied().collect();
+        while let Some(node) = stack.pop() {
+            if let Some(light) = self.nodes[node].light {
+                instances.push((node, light));
+            }
+            stack.extend(self.nodes[node].children.iter().rev());
+        }
+        instances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two root nodes, each the only node of its scene
+    const TWO_SCENES: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 1,
+        "scenes": [{ "nodes": [0] }, { "nodes": [1] }],
+        "nodes": [{}, {}]
+    }"#;
+
+    fn document() -> gltf::Document {
+        gltf::Gltf::from_slice(TWO_SCENES.as_bytes())
+            .unwrap()
+            .document
+    }
+
+    #[test]
+    fn picks_the_requested_or_default_scene() {
+        assert_eq!(
+            SceneGraph::from_document(&document(), Some(0))
+                .unwrap()
+                .roots,
+            [0]
+        );
+        assert_eq!(
+            SceneGraph::from_document(&document(), None).unwrap().roots,
+            [1]
+        );
+    }
+
+    #[test]
+    fn rejects_a_scene_out_of_range() {
+        match SceneGraph::from_document(&document(), Some(2)) {
+            Err(RendererError::InvalidArgument(reason)) => {
+                assert_eq!(reason, "no scene 2, the asset has 2")
+            }
+            other => panic!(
+                "expected InvalidArgument, got {:?}",
+                other.map(|graph| graph.roots)
+            ),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// Depth pass from the key light into the shadow map (see shadows.rs). The fragment stage only
+// cuts out the transparent parts of masked and blended materials.
+
+// === Camera + model uniform, only the per draw model matrix, skinning and morphing are used ===
+struct Uniforms {
+    viewProj   : mat4x4<f32>,
+    model      : mat4x4<f32>,
+    cameraPos  : vec3<f32>,
+    skinned    : u32,
+    morphWeights     : array<vec4<f32>, 2>,
+    morphIndices     : array<vec4<u32>, 2>,
+    morphBase        : u32,
+    morphVertexStart : u32,
+    morphVertexCount : u32,
+    morphCount       : u32,
+};
+@group(0) @binding(0)
+var<uniform> uniforms : Uniforms;
+
+// === Morph target deltas (morph_targets.rs), 3 texels per vertex and target ===
+@group(0) @binding(2)
+var morphDeltas : texture_2d<f32>;
+
+fn morph_delta(texel : u32) -> vec3<f32> {
+    let width = textureDimensions(morphDeltas).x;
+    return textureLoad(morphDeltas, vec2<u32>(texel % width, texel / width), 0).xyz;
+}
+
+// Texel of the position delta of `vertex` in active morph target slot `i`
+fn morph_texel(vertex : u32, i : u32) -> u32 {
+    let index = uniforms.morphIndices[i / 4u][i % 4u];
+    return uniforms.morphBase + (index * uniforms.morphVertexCount + vertex - uniforms.morphVertexStart) * 3u;
+}
+
+fn morph_weight(i : u32) -> f32 {
+    return uniforms.morphWeights[i / 4u][i % 4u];
+}
+
+// === Joint palette of the draw's skin (skinning.rs), read when uniforms.skinned is set ===
+const MAX_JOINTS : u32 = 256u;
+struct Joints {
+    matrices : array<mat4x4<f32>, MAX_JOINTS>,
+};
+@group(0) @binding(1)
+var<uniform> joints : Joints;
+
+// Blends the joints' matrices by the vertex weights; joint matrices already include the
+// skeleton's world transform
+fn skin_matrix(joint : vec4<u32>, weight : vec4<f32>) -> mat4x4<f32> {
+    let j = min(joint, vec4<u32>(MAX_JOINTS - 1u));
+    return joints.matrices[j.x] * weight.x
+         + joints.matrices[j.y] * weight.y
+         + joints.matrices[j.z] * weight.z
+         + joints.matrices[j.w] * weight.w;
+}
+
+// === Material (group 1, the PBR pipeline's material bind group), only alpha is used ===
+struct Material {
+    baseColorFactor          : vec4<f32>,
+    emissiveFactor           : vec3<f32>,
+    metallicFactor           : f32,
+    roughnessFactor          : f32,
+    baseColorTexture         : u32,
+    metallicRoughnessTexture : u32,
+    normalTexture            : u32,
+    occlusionTexture         : u32,
+    emissiveTexture          : u32,
+    alphaCutoff              : f32,
+    alphaMode                : u32,   // 0 opaque, 1 mask, 2 blend
+    doubleSided              : u32,
+    normalScale              : f32,
+    _pad                     : vec2<u32>,
+};
+@group(1) @binding(0)
+var<uniform> material : Material;
+@group(1) @binding(1) var baseColorTex : texture_2d<f32>;
+@group(1) @binding(2) var baseColorSampler : sampler;
+
+// blended surfaces cast a shadow where they are at least this opaque
+const BLEND_SHADOW_CUTOFF : f32 = 0.5;
+
+struct Shadow {
+    lightViewProj : mat4x4<f32>,
+    toLight       : vec3<f32>,
+    bias          : f32,
+    normalBias    : f32,
+    pcfRadius     : i32,
+    enabled       : u32,
+    texelSize     : f32,
+    strength      : f32,
+    keyLight      : i32,
+    _pad          : vec2<f32>,
+};
+@group(2) @binding(0)
+var<uniform> shadow : Shadow;
+
+struct VSOut {
+    @builtin(position) position : vec4<f32>,
+    @location(0) uv : vec2<f32>,
+    @location(1) color : vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position : vec3<f32>,
+           @location(3) uv : vec2<f32>,
+           @location(4) color : vec4<f32>,
+           @location(5) joint : vec4<u32>,
+           @location(6) weight : vec4<f32>,
+           @builtin(vertex_index) vertex : u32) -> VSOut {
+    var morphed = position;
+    for (var i = 0u; i < uniforms.morphCount; i++) {
+        morphed += morph_weight(i) * morph_delta(morph_texel(vertex, i));
+    }
+    var model = uniforms.model;
+    if (uniforms.skinned != 0u) {
+        model = skin_matrix(joint, weight);
+    }
+    var out : VSOut;
+    out.position = shadow.lightViewProj * model * vec4<f32>(morphed, 1.0);
+    out.uv = uv;
+    out.color = color;
+    return out;
+}
+
+// Same base color alpha as the PBR fragment shader
+@fragment
+fn fs_main(in : VSOut) {
+    if (material.alphaMode == 0u) {
+        return;
+    }
+    var alpha = material.baseColorFactor.a * textureSample(baseColorTex, baseColorSampler, in.uv).a;
+    if (any(in.color.rgb != vec3<f32>(0.0))) {
+        alpha *= in.color.a;
+    }
+    let cutoff = select(BLEND_SHADOW_CUTOFF, material.alphaCutoff, material.alphaMode == 1u);
+    if (alpha < cutoff) {
+        discard;
+    }
+}
@@ -0,0 +1,488 @@
+use crate::camera_math::Camera;
+use crate::custom_structs::CustomStructs::{
+    GroundUniform, Model, ModelResources, ShadowUniform, Vertex,
+};
+use crate::lights::{Light, LightKind};
+#[cfg(target_arch = "wasm32")]
+use crate::renderer_error::RendererError;
+use glam::{Mat4, Vec3};
+
+// Side of the square shadow map; WebGL2-level devices stop at 2048
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    // depth bias in light clip space, the shadow volume spans 0..1
+    pub bias: f32,
+    // offset of the receiving point along its normal, in shadow map texels
+    pub normal_bias: f32,
+    // PCF kernel radius in texels, 0 = hard edges
+    pub softness: u32,
+    // 0..1, how much environment light a shadow takes away when no directional light casts it
+    pub strength: f32,
+    // shadow catcher floor under the model
+    pub ground: bool,
+    pub ground_opacity: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            enabled: true,
+            bias: 0.002,
+            normal_bias: 1.5,
+            softness: 2,
+            strength: 0.6,
+            ground: false,
+            ground_opacity: 0.5,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl ShadowSettings {
+    // { enabled, bias, normalBias, softness, strength, ground, groundOpacity }; missing keys
+    // keep their defaults
+    pub fn from_js(value: &wasm_bindgen::JsValue) -> Result<ShadowSettings, RendererError> {
+        if !value.is_object() {
+            return Err(RendererError::InvalidArgument(
+                "shadow settings must be an object".to_string(),
+            ));
+        }
+        let get = |key: &str| js_sys::Reflect::get(value, &key.into()).unwrap_or_default();
+        let number = |key: &str, default: f32| get(key).as_f64().map_or(default, |n| n as f32);
+        let flag = |key: &str, default: bool| get(key).as_bool().unwrap_or(default);
+        let defaults = ShadowSettings::default();
+        Ok(ShadowSettings {
+            enabled: flag("enabled", defaults.enabled),
+            bias: number("bias", defaults.bias),
+            normal_bias: number("normalBias", defaults.normal_bias),
+            softness: number("softness", defaults.softness as f32).max(0.0) as u32,
+            strength: number("strength", defaults.strength).clamp(0.0, 1.0),
+            ground: flag("ground", defaults.ground),
+            ground_opacity: number("groundOpacity", defaults.ground_opacity).clamp(0.0, 1.0),
+        })
+    }
+}
+
+// Shadow map of the key light: the first directional light, or the brightest direction of
+// the environment when there is none. Rendered before the main pass, sampled with PCF by the
+// PBR shader (group 2) and by the optional ground plane drawn after the model.
+pub struct Shadows {
+    pub settings: ShadowSettings,
+    pub map_view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    // ShadowUniform, shared by the shadow pass, the PBR shader and the ground
+    pub uniform_buffer: wgpu::Buffer,
+    pass_layout: wgpu::BindGroupLayout,
+    pass_bind_group: wgpu::BindGroup,
+    ground_pipeline: wgpu::RenderPipeline,
+    ground_buffer: wgpu::Buffer,
+    ground_bind_group: wgpu::BindGroup,
+    // false while disabled or without geometry, the map is then neither drawn nor sampled
+    active: bool,
+}
+
+impl Shadows {
+    // `format` and `sample_count` are the scene pass' the ground is drawn in
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, sample_count: u32) -> Shadows {
+        let map_view = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Shadow Map"),
+                size: wgpu::Extent3d {
+                    width: SHADOW_MAP_SIZE,
+                    height: SHADOW_MAP_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: SHADOW_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        // linear filtering of the comparison result gives one extra texel of softness for free
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Uniform"),
+            size: std::mem::size_of::<ShadowUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let ground_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Ground Uniform"),
+            size: std::mem::size_of::<GroundUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // group 2 of the shadow pass, groups 0 and 1 are the model's camera BG for the node
+        // matrices and its material BGs for alpha
+        let pass_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Pass BGL"),
+            entries: &[uniform_entry(0, wgpu::ShaderStages::VERTEX)],
+        });
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Pass BG"),
+            layout: &pass_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let ground_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ground BGL"),
+            entries: &[
+                uniform_entry(0, wgpu::ShaderStages::VERTEX_FRAGMENT),
+                uniform_entry(1, wgpu::ShaderStages::FRAGMENT),
+                map_entry(2),
+                sampler_entry(3),
+            ],
+        });
+        let ground_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ground BG"),
+            layout: &ground_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: ground_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&map_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        let ground_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ground Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("ground_shader.wgsl").into()),
+        });
+        let ground_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Ground Pipeline Layout"),
+                bind_group_layouts: &[&ground_layout],
+                push_constant_ranges: &[],
+            });
+        let ground_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Ground Pipeline"),
+            layout: Some(&ground_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &ground_shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &ground_shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // same blending as the PBR pipeline so transparent backgrounds keep the shadow
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendState::ALPHA_BLENDING.color,
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // hidden by the model where it is in front, but never hides anything itself
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth24Plus,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        Shadows {
+            settings: ShadowSettings::default(),
+            map_view,
+            sampler,
+            uniform_buffer,
+            pass_layout,
+            pass_bind_group,
+            ground_pipeline,
+            ground_buffer,
+            ground_bind_group,
+            active: false,
+        }
+    }
+
+    // Depth pipeline of the shadow pass for a model's camera and material BGLs; masked and
+    // blended materials are cut out by their base color alpha
+    pub fn build_pipeline(
+        &self,
+        device: &wgpu::Device,
+        camera_bgl: &wgpu::BindGroupLayout,
+        material_bgl: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shadow_shader.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[camera_bgl, material_bgl, &self.pass_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    // POSITION @location(0), TEXCOORD_0 and COLOR_0 for alpha, JOINTS_0 and
+                    // WEIGHTS_0 for skinned draws
+                    attributes: &[
+                        wgpu::VertexAttribute {
+                            offset: 0,
+                            shader_location: 0,
+                            format: wgpu::VertexFormat::Float32x3,
+                        },
+                        wgpu::VertexAttribute {
+                            offset: std::mem::offset_of!(Vertex, uv) as wgpu::BufferAddress,
+                            shader_location: 3,
+                            format: wgpu::VertexFormat::Float32x2,
+                        },
+                        wgpu::VertexAttribute {
+                            offset: std::mem::offset_of!(Vertex, color) as wgpu::BufferAddress,
+                            shader_location: 4,
+                            format: wgpu::VertexFormat::Float32x4,
+                        },
+                        wgpu::VertexAttribute {
+                            offset: std::mem::offset_of!(Vertex, joints) as wgpu::BufferAddress,
+                            shader_location: 5,
+                            format: wgpu::VertexFormat::Uint32x4,
+                        },
+                        wgpu::VertexAttribute {
+                            offset: std::mem::offset_of!(Vertex, weights) as wgpu::BufferAddress,
+                            shader_location: 6,
+                            format: wgpu::VertexFormat::Float32x4,
+                        },
+                    ],
+                }],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[],
+                compilation_options: Default::default(),
+            }),
+            // both faces cast, thin and single sided geometry included
+            primitive: wgpu::PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Fits the light's orthographic view around the model (and the ground) and uploads it.
+    // `lights` are the lights in LightsUniform order, `environment_key` the environment's
+    // brightest direction.
+    pub fn update(
+        &mut self,
+        queue: &wgpu::Queue,
+        model: &Model,
+        lights: &[Light],
+        environment_key: Vec3,
+        camera: &Camera,
+    ) {
+        let bounds = model.world_bounds().filter(|_| self.settings.enabled);
+        self.active = bounds.is_some();
+        let (min, max) = bounds.unwrap_or((Vec3::ZERO, Vec3::ZERO));
+        let key = lights
+            .iter()
+            .position(|light| light.kind == LightKind::Directional);
+        let to_light = match key {
+            Some(i) => -lights[i].direction,
+            // keep the environment's key above the horizon so shadows fall onto the floor
+            None => {
+                let key = environment_key.normalize_or(Vec3::Y);
+                Vec3::new(key.x, key.y.max(0.25), key.z).normalize()
+            }
+        }
+        .normalize_or(Vec3::Y);
+
+        let center = (min + max) * 0.5;
+        let model_radius = ((max - min).length() * 0.5).max(0.001);
+        // the ground reaches twice the model's radius out and has to fit in the map as well
+        let ground_half_size = model_radius * 2.0;
+        let radius = if self.settings.ground {
+            model_radius * 2.5
+        } else {
+            model_radius
+        };
+        let up = if to_light.y.abs() > 0.99 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+        let view = Mat4::look_at_rh(center + to_light * radius * 2.0, center, up);
+        let projection =
+            Mat4::orthographic_rh(-radius, radius, -radius, radius, radius, radius * 3.0);
+        let texel_world = 2.0 * radius / SHADOW_MAP_SIZE as f32;
+
+        let uniform = ShadowUniform {
+            light_view_proj: (projection * view).to_cols_array_2d(),
+            to_light: to_light.to_array(),
+            bias: self.settings.bias,
+            normal_bias: self.settings.normal_bias * texel_world,
+            pcf_radius: self.settings.softness.min(4) as i32,
+            enabled: self.active as u32,
+            texel_size: 1.0 / SHADOW_MAP_SIZE as f32,
+            strength: self.settings.strength.clamp(0.0, 1.0),
+            key_light: key.map_or(-1, |i| i as i32),
+            _pad: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        let ground = GroundUniform {
+            view_proj: camera.view_proj_matrix().to_cols_array_2d(),
+            center: [center.x, min.y, center.z],
+            half_size: ground_half_size,
+            opacity: self.settings.ground_opacity.clamp(0.0, 1.0),
+            _pad: [0.0; 3],
+        };
+        queue.write_buffer(&self.ground_buffer, 0, bytemuck::bytes_of(&ground));
+    }
+
+    // Records the depth-only pass into the shadow map, before the main pass
+    pub fn draw_map(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        resources: &ModelResources,
+        camera_stride: u64,
+    ) {
+        if !self.active {
+            return;
+        }
+        let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.map_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        shadow_pass.set_pipeline(&resources.shadow_pipeline);
+        shadow_pass.set_bind_group(2, Some(&self.pass_bind_group), &[]);
+        let model = &resources.model;
+        let default_material = resources.material_bind_groups.last();
+        shadow_pass.set_vertex_buffer(0, model.vertex_buffer.slice(..));
+        shadow_pass.set_index_buffer(model.indice_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for (i, draw) in model.draws.iter().enumerate() {
+            let primitive = &model.primitives[draw.primitive];
+            shadow_pass.set_bind_group(
+                0,
+                Some(&resources.camera_bind_group),
+                &model.dynamic_offsets(i, camera_stride),
+            );
+            let material_bind_group = primitive
+                .material_index
+                .and_then(|m| resources.material_bind_groups.get(m))
+                .or(default_material);
+            shadow_pass.set_bind_group(1, material_bind_group, &[]);
+            shadow_pass.draw_indexed(
+                primitive.first_index..primitive.first_index + primitive.index_count,
+                0,
+                0..1,
+            );
+        }
+    }
+
+    // Records the shadow catcher in the main pass, after the opaque and masked draws and before
+    // the blended ones
+    pub fn draw_ground(&self, render_pass: &mut wgpu::RenderPass) {
+        if !self.active || !self.settings.ground {
+            return;
+        }
+        render_pass.set_pipeline(&self.ground_pipeline);
+        render_pass.set_bind_group(0, Some(&self.ground_bind_group), &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
+
+fn uniform_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// The shadow map and its comparison sampler, also used by the PBR light BGL
+pub(crate) fn map_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Depth,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn sampler_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+        count: None,
+    }
+}
@@ -0,0 +1,65 @@
+use crate::custom_structs::CustomStructs::JointsUniform;
+use crate::scene_graph::SceneGraph;
+use bytemuck::Zeroable;
+use glam::Mat4;
+
+// WebGL2-level devices have no storage buffers, joint palettes live in a fixed size uniform
+// array. 256 matrices fill the 16 KiB WebGL2 guarantees for one uniform block.
+pub const MAX_JOINTS: usize = 256;
+
+// One glTF skin: the joint nodes and the matrices that take mesh space into each joint's space
+#[derive(Debug, Clone)]
+pub struct Skin {
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+impl Skin {
+    pub fn from_gltf(skin: &gltf::Skin, buffers: &[gltf::buffer::Data]) -> Skin {
+        let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+        // without the accessor every inverse bind matrix is the identity
+        let mut inverse_bind_matrices: Vec<Mat4> = skin
+            .reader(|buffer| Some(&buffers[buffer.index()]))
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(|m| Mat4::from_cols_array_2d(&m)).collect())
+            .unwrap_or_default();
+        inverse_bind_matrices.resize(joints.len(), Mat4::IDENTITY);
+        Skin {
+            joints,
+            inverse_bind_matrices,
+        }
+    }
+
+    // Matrices taking mesh space to world space through each joint, for the scene's current
+    // world matrices. Skinned vertices end up in world space, glTF ignores the transform of the
+    // node that instances a skinned mesh.
+    pub fn joint_matrices<'a>(&'a self, scene: &'a SceneGraph) -> impl Iterator<Item = Mat4> + 'a {
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .take(MAX_JOINTS)
+            .map(|(&joint, inverse_bind)| {
+                scene.world.get(joint).copied().unwrap_or(Mat4::IDENTITY) * *inverse_bind
+            })
+    }
+
+    pub fn palette(&self, scene: &SceneGraph) -> JointsUniform {
+        let mut uniform = JointsUniform::zeroed();
+        for (slot, matrix) in self.joint_matrices(scene).enumerate() {
+            uniform.matrices[slot] = matrix.to_cols_array_2d();
+        }
+        uniform
+    }
+}
+
+// One JointsUniform per skin, each at its own dynamic offset. Models without skins still get
+// a slot so the bind group has something to point at.
+pub fn joint_palette_bytes(skins: &[Skin], scene: &SceneGraph) -> Vec<u8> {
+    if skins.is_empty() {
+        return bytemuck::bytes_of(&JointsUniform::zeroed()).to_vec();
+    }
+    skins
+        .iter()
+        .flat_map(|skin| bytemuck::bytes_of(&skin.palette(scene)).to_vec())
+        .collect()
+}
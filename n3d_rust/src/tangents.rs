@@ -0,0 +1,106 @@
+use glam::{Vec2, Vec3};
+
+// Per-vertex tangents (xyz, w = bitangent sign) for a triangle list that has normals and UVs
+// but no TANGENT attribute. This is not MikkTSpace, only its conventions (v pointing up,
+// bitangent = cross(normal, tangent) * w): each corner adds its triangle's UV-space tangent,
+// projected into the vertex normal's plane and weighted by the corner angle. MikkTSpace also
+// splits vertices where tangent frames disagree and orthogonalizes per face, so normal maps
+// baked with it can show small shading differences on curved or mirrored UV layouts.
+pub fn angle_weighted_tangents(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    uvs: &[[f32; 2]],
+    indices: &[u32],
+) -> Vec<[f32; 4]> {
+    let count = positions.len();
+    let mut tangent_sums = vec![Vec3::ZERO; count];
+    let mut bitangent_sums = vec![Vec3::ZERO; count];
+    let normal = |i: usize| Vec3::from_array(normals[i]).normalize_or_zero();
+
+    for triangle in indices.chunks_exact(3) {
+        let corners = [
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        ];
+        if corners.iter().any(|&i| i >= count) {
+            continue;
+        }
+        let p = corners.map(|i| Vec3::from_array(positions[i]));
+        // tangent space has v pointing up, glTF texture coordinates point down
+        let uv = corners.map(|i| Vec2::new(uvs[i][0], 1.0 - uvs[i][1]));
+        let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+        let (d1, d2) = (uv[1] - uv[0], uv[2] - uv[0]);
+        let det = d1.x * d2.y - d2.x * d1.y;
+        if det.abs() < 1e-12 {
+            // no UV area, nothing to orient a tangent by
+            continue;
+        }
+        let tangent = (e1 * d2.y - e2 * d1.y) / det;
+        let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+
+        for corner in 0..3 {
+            let i = corners[corner];
+            let to_next = (p[(corner + 1) % 3] - p[corner]).normalize_or_zero();
+            let to_prev = (p[(corner + 2) % 3] - p[corner]).normalize_or_zero();
+            let angle = to_next.dot(to_prev).clamp(-1.0, 1.0).acos();
+            let n = normal(i);
+            tangent_sums[i] += (tangent - n * n.dot(tangent)).normalize_or_zero() * angle;
+            bitangent_sums[i] += (bitangent - n * n.dot(bitangent)).normalize_or_zero() * angle;
+        }
+    }
+
+    (0..count)
+        .map(|i| {
+            let n = normal(i);
+            let mut t = (tangent_sums[i] - n * n.dot(tangent_sums[i])).normalize_or_zero();
+            if t == Vec3::ZERO {
+                // unreferenced or UV-less vertex: any direction in the normal's plane
+                t = if n == Vec3::ZERO {
+                    Vec3::X
+                } else {
+                    n.any_orthonormal_vector()
+                };
+            }
+            let w = if n.cross(t).dot(bitangent_sums[i]) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            [t.x, t.y, t.z, w]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // unit quad in the XY plane facing +Z, `mirrored` runs u from right to left
+    fn quad_tangents(mirrored: bool) -> Vec<[f32; 4]> {
+        let positions = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        let normals = [[0.0, 0.0, 1.0]; 4];
+        let u = |x: f32| if mirrored { 1.0 - x } else { x };
+        let uvs = [[u(0.0), 1.0], [u(1.0), 1.0], [u(1.0), 0.0], [u(0.0), 0.0]];
+        angle_weighted_tangents(&positions, &normals, &uvs, &[0, 1, 2, 0, 2, 3])
+    }
+
+    #[test]
+    fn quad_tangents_follow_u() {
+        for tangent in quad_tangents(false) {
+            assert_eq!(tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn mirrored_quad_flips_bitangent_sign() {
+        for tangent in quad_tangents(true) {
+            assert_eq!(tangent, [-1.0, 0.0, 0.0, -1.0]);
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::custom_structs::CustomStructs::ToneMappingUniform;
+use crate::logger;
+use crate::saved_state::SavedState;
+
+// Preferred format of the HDR target; needs float render targets with blending
+// (EXT_color_buffer_float and EXT_float_blend on WebGL2)
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Maps the HDR frame into the displayable 0..1 range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapping {
+    // clamps, highlights clip
+    None,
+    // filmic, punchy contrast and saturation
+    Aces,
+    // Khronos PBR Neutral, keeps base colors as authored (best for products)
+    #[default]
+    PbrNeutral,
+    // filmic, desaturates bright highlights gracefully
+    AgX,
+    Reinhard,
+}
+
+impl ToneMapping {
+    // "none", "aces", "neutral", "agx" or "reinhard"
+    pub fn from_name(name: &str) -> Option<ToneMapping> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" | "linear" => Some(ToneMapping::None),
+            "aces" => Some(ToneMapping::Aces),
+            "neutral" | "pbr-neutral" | "khronos-pbr-neutral" => Some(ToneMapping::PbrNeutral),
+            "agx" => Some(ToneMapping::AgX),
+            "reinhard" => Some(ToneMapping::Reinhard),
+            _ => None,
+        }
+    }
+
+    // `mode` in tone_mapping_shader.wgsl
+    fn shader_mode(self) -> u32 {
+        match self {
+            ToneMapping::None => 0,
+            ToneMapping::Aces => 1,
+            ToneMapping::PbrNeutral => 2,
+            ToneMapping::AgX => 3,
+            ToneMapping::Reinhard => 4,
+        }
+    }
+}
+
+// The HDR target the model is drawn into and the pass that tone maps it onto the surface
+pub struct ToneMapper {
+    pub mapping: ToneMapping,
+    // linear multiplier on the scene, 1 = as lit
+    pub exposure: f32,
+    // HDR_FORMAT, or Rgba8Unorm when the device cannot render and blend floats
+    pub hdr_format: wgpu::TextureFormat,
+    // MSAA samples of the scene pass, every pipeline drawn in it has to match
+    pub sample_count: u32,
+    pub hdr_view: wgpu::TextureView,
+    // multisampled color target resolved into hdr_view, None without MSAA
+    msaa_view: Option<wgpu::TextureView>,
+    encode_srgb: bool,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ToneMapper {
+    // Targets sized like the surface; when its format is not sRGB the pass encodes sRGB itself.
+    // `msaa_samples` is 1 or 4 and falls back to 1 if the formats can't be multisampled.
+    pub fn new(saved_gpu: &SavedState, msaa_samples: u32) -> ToneMapper {
+        let device = saved_gpu.get_device();
+        let format = saved_gpu.get_surface_format();
+        let (width, height) = saved_gpu.get_surface_size();
+        let hdr_features = saved_gpu.format_features(HDR_FORMAT);
+        let hdr_format = if hdr_features
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            && hdr_features
+                .flags
+                .contains(wgpu::TextureFormatFeatureFlags::BLENDABLE)
+        {
+            HDR_FORMAT
+        } else {
+            logger::log("no blendable float render targets, highlights above 1.0 will clip");
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        // WebGPU only knows 1 and 4 samples
+        let requested = if msaa_samples > 1 { 4 } else { 1 };
+        let color_flags = saved_gpu.format_features(hdr_format).flags;
+        let depth_flags = saved_gpu
+            .format_features(wgpu::TextureFormat::Depth24Plus)
+            .flags;
+        let sample_count = if requested == 1
+            || (color_flags.sample_count_supported(requested)
+                && color_flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                && depth_flags.sample_count_supported(requested))
+        {
+            requested
+        } else {
+            logger::log(&format!(
+                "{}x MSAA is not supported for {:?}, rendering without",
+                requested, hdr_format
+            ));
+            1
+        };
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tone Mapping Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("tone_mapping_shader.wgsl").into()),
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tone Mapping BGL"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // read with textureLoad, one texel per pixel
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tone Mapping Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tone Mapping Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // premultiplied frame over the background already in the surface
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING.color,
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tone Mapping Uniform"),
+            size: std::mem::size_of::<ToneMappingUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let hdr_view = create_hdr_view(&device, hdr_format, width, height, 1);
+        let msaa_view = (sample_count > 1)
+            .then(|| create_hdr_view(&device, hdr_format, width, height, sample_count));
+        let bind_group = create_bind_group(&device, &layout, &uniform_buffer, &hdr_view);
+        ToneMapper {
+            mapping: ToneMapping::default(),
+            exposure: 1.0,
+            hdr_format,
+            sample_count,
+            hdr_view,
+            msaa_view,
+            encode_srgb: !format.is_srgb(),
+            layout,
+            pipeline,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // Recreates the HDR targets, sizes in physical pixels like the surface
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.hdr_view = create_hdr_view(device, self.hdr_format, width, height, 1);
+        self.msaa_view = (self.sample_count > 1)
+            .then(|| create_hdr_view(device, self.hdr_format, width, height, self.sample_count));
+        self.bind_group =
+            create_bind_group(device, &self.layout, &self.uniform_buffer, &self.hdr_view);
+    }
+
+    // Color attachment of the scene pass, cleared to transparent; with MSAA the samples are
+    // resolved into hdr_view at the end of the pass and then discarded
+    pub fn scene_attachment(&self) -> wgpu::RenderPassColorAttachment<'_> {
+        let (view, resolve_target, store) = match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&self.hdr_view), wgpu::StoreOp::Discard),
+            None => (&self.hdr_view, None, wgpu::StoreOp::Store),
+        };
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store,
+            },
+            depth_slice: None,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let uniform = ToneMappingUniform {
+            exposure: self.exposure.max(0.0),
+            mode: self.mapping.shader_mode(),
+            encode_srgb: self.encode_srgb as u32,
+            _pad: 0.0,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    // Records the fullscreen triangle into the surface pass, after the background
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, Some(&self.bind_group), &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_hdr_view(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> wgpu::TextureView {
+    // the multisampled target is only rendered to and resolved
+    let usage = if sample_count > 1 {
+        wgpu::TextureUsages::RENDER_ATTACHMENT
+    } else {
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+    };
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(if sample_count > 1 {
+                "HDR MSAA Target"
+            } else {
+                "HDR Target"
+            }),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    hdr_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Tone Mapping BG"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(hdr_view),
+            },
+        ],
+    })
+}
@@ -0,0 +1,115 @@
+// Final pass: HDR target -> exposure -> tone mapper -> display, drawn over the
+// display referred background (see tone_mapping.rs).
+
+struct ToneMapping {
+    exposure   : f32,
+    mode       : u32,   // 0 none, 1 ACES, 2 Khronos PBR Neutral, 3 AgX, 4 Reinhard
+    encodeSrgb : u32,   // 1 when the surface is not an sRGB format
+    _pad       : f32,
+};
+@group(0) @binding(0) var<uniform> toneMapping : ToneMapping;
+@group(0) @binding(1) var hdrFrame : texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> @builtin(position) vec4<f32> {
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// ACES filmic, Stephen Hill's fit of the RRT + ODT (as in the glTF sample viewer)
+fn tone_map_aces(color : vec3<f32>) -> vec3<f32> {
+    let input = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+    // the fit expects scene values 0.6x brighter than the sample viewer's other mappers
+    let v = input * (color / 0.6);
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return output * (a / b);
+}
+
+// Khronos PBR Neutral: keeps base colors up to ~0.8 untouched, compresses highlights
+fn tone_map_pbr_neutral(color : vec3<f32>) -> vec3<f32> {
+    let startCompression = 0.8 - 0.04;
+    let desaturation = 0.15;
+    let x = min(color.r, min(color.g, color.b));
+    let offset = select(0.04, x - 6.25 * x * x, x < 0.08);
+    var c = color - offset;
+    let peak = max(c.r, max(c.g, c.b));
+    if (peak < startCompression) {
+        return c;
+    }
+    let d = 1.0 - startCompression;
+    let newPeak = 1.0 - d * d / (peak + d - startCompression);
+    c *= newPeak / peak;
+    let g = 1.0 - 1.0 / (desaturation * (peak - newPeak) + 1.0);
+    return mix(c, vec3<f32>(newPeak), g);
+}
+
+// AgX with the default (Blender) look, in Rec. 2020 like three.js
+fn tone_map_agx(color : vec3<f32>) -> vec3<f32> {
+    let srgbToRec2020 = mat3x3<f32>(
+        vec3<f32>(0.6274, 0.0691, 0.0164),
+        vec3<f32>(0.3293, 0.9195, 0.0880),
+        vec3<f32>(0.0433, 0.0113, 0.8956),
+    );
+    let rec2020ToSrgb = mat3x3<f32>(
+        vec3<f32>(1.6605, -0.1246, -0.0182),
+        vec3<f32>(-0.5876, 1.1329, -0.1006),
+        vec3<f32>(-0.0728, -0.0083, 1.1187),
+    );
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.856627153315983, 0.137318972929847, 0.11189821299995),
+        vec3<f32>(0.0951212405381588, 0.761241990602591, 0.0767994186031903),
+        vec3<f32>(0.0482516061458583, 0.101439036467562, 0.811302368396859),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.1271005818144368, -0.1413297634984383, -0.14132976349843826),
+        vec3<f32>(-0.11060664309660323, 1.157823702216272, -0.11060664309660294),
+        vec3<f32>(-0.016493938717834573, -0.016493938717834257, 1.2519364065950405),
+    );
+    let minEv = -12.47393;
+    let maxEv = 4.026069;
+    var c = inset * (srgbToRec2020 * color);
+    c = clamp((log2(max(c, vec3<f32>(1e-10))) - minEv) / (maxEv - minEv), vec3<f32>(0.0), vec3<f32>(1.0));
+    // sigmoid contrast curve
+    let x2 = c * c;
+    let x4 = x2 * x2;
+    c = 15.5 * x4 * x2 - 40.14 * x4 * c + 31.96 * x4 - 6.868 * x2 * c + 0.4298 * x2 + 0.1191 * c - 0.00232;
+    c = pow(max(outset * c, vec3<f32>(0.0)), vec3<f32>(2.2));
+    return rec2020ToSrgb * c;
+}
+
+fn linear_to_srgb(c : vec3<f32>) -> vec3<f32> {
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(@builtin(position) position : vec4<f32>) -> @location(0) vec4<f32> {
+    let hdr = textureLoad(hdrFrame, vec2<i32>(position.xy), 0);
+    if (hdr.a <= 0.0) {
+        return vec4<f32>(0.0);
+    }
+    // the HDR target holds premultiplied color, tone map the straight one
+    let color = hdr.rgb / hdr.a * toneMapping.exposure;
+    var mapped : vec3<f32>;
+    switch toneMapping.mode {
+        case 1u: { mapped = tone_map_aces(color); }
+        case 2u: { mapped = tone_map_pbr_neutral(color); }
+        case 3u: { mapped = tone_map_agx(color); }
+        case 4u: { mapped = color / (1.0 + color); }
+        default: { mapped = color; }
+    }
+    mapped = clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0));
+    if (toneMapping.encodeSrgb != 0u) {
+        mapped = linear_to_srgb(mapped);
+    }
+    return vec4<f32>(mapped * hdr.a, hdr.a);
+}
@@ -0,0 +1,268 @@
+// Checks that every #[repr(C)] uniform struct in custom_structs.rs has the size and field
+// offsets of its WGSL counterpart as naga lays it out. Only compiled for tests.
+
+use crate::custom_structs::CustomStructs::*;
+use std::mem::{offset_of, size_of};
+
+// Size and field offsets of a #[repr(C)] struct that is uploaded to a WGSL uniform
+#[derive(Debug, Clone)]
+struct HostLayout {
+    name: &'static str,
+    size: usize,
+    // padding fields (leading `_`) are left out, WGSL may split or merge them
+    fields: Vec<(&'static str, usize)>,
+}
+
+// A WGSL struct that has to be laid out exactly like `host`
+#[derive(Debug, Clone)]
+struct SharedStruct {
+    host: HostLayout,
+    shader: &'static str,
+    source: &'static str,
+    wgsl_struct: &'static str,
+}
+
+macro_rules! host_layout {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        HostLayout {
+            name: stringify!($ty),
+            size: size_of::<$ty>(),
+            fields: vec![$((stringify!($field), offset_of!($ty, $field))),*],
+        }
+    };
+}
+
+// Every host struct next to each WGSL struct it mirrors. uniform_structs_match_wgsl compares
+// them with naga's layout of the shaders, matching fields by name (camelCase in WGSL).
+fn shared_structs() -> Vec<SharedStruct> {
+    let camera = || {
+        host_layout!(CameraUniform {
+            view_proj,
+            model,
+            camera_pos,
+            skinned,
+            morph_weights,
+            morph_indices,
+            morph_base,
+            morph_vertex_start,
+            morph_vertex_count,
+            morph_count,
+        })
+    };
+    let material = || {
+        host_layout!(MaterialPBR {
+            base_color_factor,
+            emissive_factor,
+            metallic_factor,
+            roughness_factor,
+            base_color_texture,
+            metallic_roughness_texture,
+            normal_texture,
+            occlusion_texture,
+            emissive_texture,
+            alpha_cutoff,
+            alpha_mode,
+            double_sided,
+            normal_scale,
+        })
+    };
+    let shadow = || {
+        host_layout!(ShadowUniform {
+            light_view_proj,
+            to_light,
+            bias,
+            normal_bias,
+            pcf_radius,
+            enabled,
+            texel_size,
+            strength,
+            key_light,
+        })
+    };
+    let shared = |host, shader, source, wgsl_struct| SharedStruct {
+        host,
+        shader,
+        source,
+        wgsl_struct,
+    };
+    let vertex = include_str!("vertex_shader.wgsl");
+    let pbr = include_str!("pbr_fragment_shader.wgsl");
+    let shadow_pass = include_str!("shadow_shader.wgsl");
+    let ground = include_str!("ground_shader.wgsl");
+    vec![
+        shared(camera(), "vertex_shader.wgsl", vertex, "Uniforms"),
+        shared(camera(), "pbr_fragment_shader.wgsl", pbr, "Uniforms"),
+        shared(camera(), "shadow_shader.wgsl", shadow_pass, "Uniforms"),
+        shared(material(), "pbr_fragment_shader.wgsl", pbr, "Material"),
+        shared(material(), "shadow_shader.wgsl", shadow_pass, "Material"),
+        shared(
+            host_layout!(JointsUniform { matrices }),
+            "vertex_shader.wgsl",
+            vertex,
+            "Joints",
+        ),
+        shared(
+            host_layout!(JointsUniform { matrices }),
+            "shadow_shader.wgsl",
+            shadow_pass,
+            "Joints",
+        ),
+        shared(
+            host_layout!(PunctualLightUniform {
+                position,
+                range,
+                direction,
+                kind,
+                color,
+                intensity,
+                angle_scale,
+                angle_offset,
+            }),
+            "pbr_fragment_shader.wgsl",
+            pbr,
+            "PunctualLight",
+        ),
+        shared(
+            host_layout!(LightsUniform { lights, count }),
+            "pbr_fragment_shader.wgsl",
+            pbr,
+            "Lights",
+        ),
+        shared(shadow(), "pbr_fragment_shader.wgsl", pbr, "Shadow"),
+        shared(shadow(), "shadow_shader.wgsl", shadow_pass, "Shadow"),
+        shared(shadow(), "ground_shader.wgsl", ground, "Shadow"),
+        shared(
+            host_layout!(EnvironmentUniform { intensity, max_lod }),
+            "pbr_fragment_shader.wgsl",
+            pbr,
+            "Environment",
+        ),
+        shared(
+            host_layout!(GroundUniform {
+                view_proj,
+                center,
+                half_size,
+                opacity,
+            }),
+            "ground_shader.wgsl",
+            ground,
+            "Ground",
+        ),
+        shared(
+            host_layout!(IblBakeParams {
+                face,
+                roughness,
+                source_lod,
+                source_size,
+                sample_count,
+            }),
+            "ibl_shader.wgsl",
+            include_str!("ibl_shader.wgsl"),
+            "BakeParams",
+        ),
+        shared(
+            host_layout!(BackgroundUniform {
+                inv_view_proj,
+                camera_pos,
+                mode,
+                top,
+                bottom,
+                lod,
+                view_aspect,
+                image_aspect,
+                encode_srgb,
+            }),
+            "background_shader.wgsl",
+            include_str!("background_shader.wgsl"),
+            "BackgroundUniform",
+        ),
+        shared(
+            host_layout!(ToneMappingUniform {
+                exposure,
+                mode,
+                encode_srgb,
+            }),
+            "tone_mapping_shader.wgsl",
+            include_str!("tone_mapping_shader.wgsl"),
+            "ToneMapping",
+        ),
+    ]
+}
+
+// "lightViewProj" -> "light_view_proj"
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+// Differences between the host struct and the WGSL struct, empty when they match
+fn mismatches(shared: &SharedStruct) -> Vec<String> {
+    let module = match naga::front::wgsl::parse_str(shared.source) {
+        Ok(module) => module,
+        Err(e) => return vec![format!("{} does not parse: {}", shared.shader, e)],
+    };
+    let mut layouter = naga::proc::Layouter::default();
+    layouter.update(module.to_ctx()).unwrap();
+    let Some((members, span)) = module.types.iter().find_map(|(_, ty)| match &ty.inner {
+        naga::TypeInner::Struct { members, span }
+            if ty.name.as_deref() == Some(shared.wgsl_struct) =>
+        {
+            Some((members, *span))
+        }
+        _ => None,
+    }) else {
+        return vec![format!(
+            "no struct {} in {}",
+            shared.wgsl_struct, shared.shader
+        )];
+    };
+
+    let host = &shared.host;
+    let mut problems = Vec::new();
+    if host.size != span as usize {
+        problems.push(format!("size {} on the host, {} in WGSL", host.size, span));
+    }
+    let wgsl_fields: Vec<(String, usize)> = members
+        .iter()
+        .filter_map(|m| Some((snake_case(m.name.as_deref()?), m.offset as usize)))
+        .filter(|(name, _)| !name.starts_with('_'))
+        .collect();
+    for (name, offset) in &wgsl_fields {
+        match host.fields.iter().find(|(host_name, _)| host_name == name) {
+            Some((_, host_offset)) if host_offset != offset => problems.push(format!(
+                "{} at offset {} on the host, {} in WGSL",
+                name, host_offset, offset
+            )),
+            Some(_) => {}
+            None => problems.push(format!("{} is only declared in WGSL", name)),
+        }
+    }
+    for (name, _) in &host.fields {
+        if !wgsl_fields.iter().any(|(wgsl_name, _)| wgsl_name == name) {
+            problems.push(format!("{} is only declared on the host", name));
+        }
+    }
+    problems
+        .into_iter()
+        .map(|problem| {
+            format!(
+                "{} vs {} in {}: {}",
+                host.name, shared.wgsl_struct, shared.shader, problem
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn uniform_structs_match_wgsl() {
+    let problems: Vec<String> = shared_structs().iter().flat_map(mismatches).collect();
+    assert!(problems.is_empty(), "\n{}", problems.join("\n"));
+}
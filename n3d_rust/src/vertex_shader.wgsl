@@ -1,11 +1,57 @@
 struct Uniforms {
-    viewProj : mat4x4<f32>,
-    model    : mat4x4<f32>,
+    viewProj  : mat4x4<f32>,
+    model     : mat4x4<f32>,
+    cameraPos : vec3<f32>,
+    skinned   : u32,    // 1 = place by joints, model is then the identity
+    // active morph targets: weights and target indices, 4 per vec4
+    morphWeights     : array<vec4<f32>, 2>,
+    morphIndices     : array<vec4<u32>, 2>,
+    morphBase        : u32,   // first texel of the primitive's deltas
+    morphVertexStart : u32,   // first vertex of the primitive
+    morphVertexCount : u32,
+    morphCount       : u32,
 };
 
 @group(0) @binding(0)
 var<uniform> uniforms : Uniforms;
 
+// === Morph target deltas (morph_targets.rs), 3 texels per vertex and target ===
+@group(0) @binding(2)
+var morphDeltas : texture_2d<f32>;
+
+fn morph_delta(texel : u32) -> vec3<f32> {
+    let width = textureDimensions(morphDeltas).x;
+    return textureLoad(morphDeltas, vec2<u32>(texel % width, texel / width), 0).xyz;
+}
+
+// Texel of the position delta of `vertex` in active morph target slot `i`
+fn morph_texel(vertex : u32, i : u32) -> u32 {
+    let index = uniforms.morphIndices[i / 4u][i % 4u];
+    return uniforms.morphBase + (index * uniforms.morphVertexCount + vertex - uniforms.morphVertexStart) * 3u;
+}
+
+fn morph_weight(i : u32) -> f32 {
+    return uniforms.morphWeights[i / 4u][i % 4u];
+}
+
+// === Joint palette of the draw's skin (skinning.rs), read when uniforms.skinned is set ===
+const MAX_JOINTS : u32 = 256u;
+struct Joints {
+    matrices : array<mat4x4<f32>, MAX_JOINTS>,
+};
+@group(0) @binding(1)
+var<uniform> joints : Joints;
+
+// Blends the joints' matrices by the vertex weights; joint matrices already include the
+// skeleton's world transform
+fn skin_matrix(joint : vec4<u32>, weight : vec4<f32>) -> mat4x4<f32> {
+    let j = min(joint, vec4<u32>(MAX_JOINTS - 1u));
+    return joints.matrices[j.x] * weight.x
+         + joints.matrices[j.y] * weight.y
+         + joints.matrices[j.z] * weight.z
+         + joints.matrices[j.w] * weight.w;
+}
+
 struct VSInput {
     @location(0) position : vec3<f32>,
     @location(1) normal   : vec3<f32>,
@@ -14,6 +60,7 @@ struct VSInput {
     @location(4) color    : vec4<f32>,
     @location(5) joints   : vec4<u32>,
     @location(6) weights  : vec4<f32>,
+    @builtin(vertex_index) vertex : u32,
 };
 
 struct VSOutput {
@@ -22,19 +69,49 @@ struct VSOutput {
     @location(1) frag_uv   : vec2<f32>,
     @location(2) frag_norm : vec3<f32>,
     @location(3) frag_col  : vec4<f32>,
+    @location(4) frag_tangent : vec4<f32>,  // world space xyz, w = bitangent sign
 };
 
+// Inverse transpose of the upper 3x3 of `m` up to a positive scale, which normalizing the
+// normal removes: its cofactor matrix, negated when `m` mirrors so normals keep facing out
+fn normal_matrix(m : mat4x4<f32>) -> mat3x3<f32> {
+    let x = m[0].xyz;
+    let y = m[1].xyz;
+    let z = m[2].xyz;
+    let cofactor = mat3x3<f32>(cross(y, z), cross(z, x), cross(x, y));
+    return cofactor * sign(dot(x, cross(y, z)));
+}
+
 @vertex
 fn vs_main(input : VSInput) -> VSOutput {
     var output : VSOutput;
 
+    // blend shapes first, then skinning
+    var position = input.position;
+    var normal = input.normal;
+    var tangent = input.tangent.xyz;
+    for (var i = 0u; i < uniforms.morphCount; i++) {
+        let texel = morph_texel(input.vertex, i);
+        let weight = morph_weight(i);
+        position += weight * morph_delta(texel);
+        normal += weight * morph_delta(texel + 1u);
+        tangent += weight * morph_delta(texel + 2u);
+    }
+
+    var model = uniforms.model;
+    if (uniforms.skinned != 0u) {
+        model = skin_matrix(input.joints, input.weights);
+    }
+
     // Transform vertex position
-    let world_pos = uniforms.model * vec4<f32>(input.position, 1.0);
+    let world_pos = model * vec4<f32>(position, 1.0);
     output.clip_pos = uniforms.viewProj * world_pos;
     output.frag_pos = world_pos.xyz;
 
-    // Transform normal (ignore inverse transpose)
-    output.frag_norm = normalize((uniforms.model * vec4<f32>(input.normal, 0.0)).xyz);
+    // Normals by the inverse transpose, so non-uniform scale keeps them perpendicular
+    output.frag_norm = normalize(normal_matrix(model) * normal);
+    // zero when the mesh has no tangents, the fragment shader then skips normal mapping
+    output.frag_tangent = vec4<f32>((model * vec4<f32>(tangent, 0.0)).xyz, input.tangent.w);
 
     // Pass UVs and color
     output.frag_uv = input.uv0;
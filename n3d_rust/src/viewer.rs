@@ -0,0 +1,526 @@
+use crate::animation::{AnimationPlayer, Clip};
+use crate::background::{Background, BackgroundMode};
+use crate::camera_math::Camera;
+use crate::config_pipeline::ConfigPipeline;
+use crate::custom_structs::CustomStructs::{
+    pipeline_index, ModelResources, ALPHA_BLEND, ALPHA_MASK, ALPHA_OPAQUE,
+};
+use crate::ibl::{Environment, HdrImage, Ibl};
+use crate::lights::{lights_uniform, Light, MAX_LIGHTS};
+use crate::logger;
+use crate::model_exec::ModelExec;
+use crate::render_loop::RenderLoop;
+use crate::renderer_error::RendererError;
+use crate::renderer_options::RendererOptions;
+use crate::saved_state::SavedState;
+use crate::shadows::{ShadowSettings, Shadows};
+use crate::skinning::joint_palette_bytes;
+use crate::tone_mapping::{ToneMapper, ToneMapping};
+use glam::Vec3;
+use std::cell::RefCell;
+
+// Platform independent part of the renderer: GPU state, camera and the loaded model.
+// The wasm Renderer and the native winit app both drive one of these.
+pub struct Viewer {
+    pub saved_gpu: SavedState,
+    pub model_manager: ModelExec,
+    pub pipeline: ConfigPipeline,
+    pub render_manager: RenderLoop,
+    pub camera: RefCell<Camera>,
+    pub shaders: (wgpu::ShaderModule, wgpu::ShaderModule, wgpu::ShaderModule),
+    pub resources: RefCell<Option<ModelResources>>,
+    pub ibl: Ibl,
+    // studio lighting until load_environment replaces it
+    pub environment: RefCell<Environment>,
+    pub background: RefCell<Background>,
+    // added through add_light, drawn after the model's own KHR_lights_punctual lights
+    pub lights: RefCell<Vec<Light>>,
+    pub shadows: RefCell<Shadows>,
+    // HDR target and the pass that maps it onto the surface
+    pub tone_mapper: RefCell<ToneMapper>,
+}
+
+impl Viewer {
+    pub async fn new(
+        saved_gpu: SavedState,
+        options: &RendererOptions,
+    ) -> Result<Viewer, RendererError> {
+        let model_manager = ModelExec::new().await;
+        let pipeline = ConfigPipeline::new().await;
+        let render_manager = RenderLoop::new().await;
+        let (width, height) = saved_gpu.get_surface_size();
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 5.0),     // eye
+            Vec3::ZERO,                   // target
+            width as f32 / height as f32, // aspect
+        )
+        .await;
+        let shaders = saved_gpu.create_shader_module().await;
+        let (device, queue) = (saved_gpu.get_device(), saved_gpu.get_queue());
+        let ibl = Ibl::new(&device, &queue).await;
+        let environment = ibl.bake(&device, &queue, HdrImage::studio())?;
+        let tone_mapper = ToneMapper::new(&saved_gpu, options.msaa_samples);
+        let background = Background::new(
+            &device,
+            &queue,
+            saved_gpu.get_surface_format(),
+            tone_mapper.hdr_format,
+            tone_mapper.sample_count,
+            &environment,
+        );
+        let shadows = Shadows::new(&device, tone_mapper.hdr_format, tone_mapper.sample_count);
+        Ok(Viewer {
+            saved_gpu,
+            model_manager,
+            pipeline,
+            render_manager,
+            camera: RefCell::new(camera),
+            shaders,
+            resources: RefCell::new(None),
+            ibl,
+            environment: RefCell::new(environment),
+            background: RefCell::new(background),
+            lights: RefCell::new(Vec::new()),
+            shadows: RefCell::new(shadows),
+            tone_mapper: RefCell::new(tone_mapper),
+        })
+    }
+
+    // Replaces the image based lighting with an equirectangular Radiance .hdr
+    pub fn load_environment(&self, hdr_bytes: &[u8]) -> Result<(), RendererError> {
+        let image = HdrImage::decode(hdr_bytes)?;
+        let environment = self.ibl.bake(
+            &self.saved_gpu.get_device(),
+            &self.saved_gpu.get_queue(),
+            image,
+        )?;
+        // keep the intensity set for the previous environment
+        let intensity = self.environment.borrow().intensity();
+        environment.set_intensity(&self.saved_gpu.get_queue(), intensity);
+        self.background
+            .borrow_mut()
+            .set_environment(&self.saved_gpu.get_device(), &environment);
+        *self.environment.borrow_mut() = environment;
+        Ok(())
+    }
+
+    pub fn set_environment_intensity(&self, intensity: f32) {
+        self.environment
+            .borrow()
+            .set_intensity(&self.saved_gpu.get_queue(), intensity);
+    }
+
+    // Transparent switches the surface to premultiplied alpha and fails if it can't;
+    // Image needs load_background_image first
+    pub fn set_background(&self, mode: BackgroundMode) -> Result<(), RendererError> {
+        if mode == BackgroundMode::Image && !self.background.borrow().has_image() {
+            return Err(RendererError::InvalidArgument(
+                "no background image loaded".to_string(),
+            ));
+        }
+        let transparent = mode == BackgroundMode::Transparent;
+        if !self.saved_gpu.set_transparent(transparent) {
+            return Err(RendererError::Unsupported(
+                "the surface cannot composite with alpha".to_string(),
+            ));
+        }
+        self.background.borrow_mut().mode = mode;
+        Ok(())
+    }
+
+    // PNG or JPEG shown behind the model; also switches the background to it
+    pub fn load_background_image(&self, bytes: &[u8]) -> Result<(), RendererError> {
+        self.background.borrow_mut().load_image(
+            &self.saved_gpu.get_device(),
+            &self.saved_gpu.get_queue(),
+            bytes,
+        )?;
+        self.set_background(BackgroundMode::Image)
+    }
+
+    // World space light on top of the model's; returns how many were added so far.
+    // Only the first MAX_LIGHTS of scene and added lights together are drawn.
+    pub fn add_light(&self, light: Light) -> usize {
+        let mut lights = self.lights.borrow_mut();
+        lights.push(light);
+        lights.len()
+    }
+
+    pub fn clear_lights(&self) {
+        self.lights.borrow_mut().clear();
+    }
+
+    pub fn set_tone_mapping(&self, mapping: ToneMapping) {
+        self.tone_mapper.borrow_mut().mapping = mapping;
+    }
+
+    // Linear multiplier on the scene before tone mapping, 1 = as lit
+    pub fn set_exposure(&self, exposure: f32) {
+        self.tone_mapper.borrow_mut().exposure = exposure;
+    }
+
+    // Takes effect from the next frame
+    pub fn set_shadows(&self, settings: ShadowSettings) {
+        self.shadows.borrow_mut().settings = settings;
+    }
+
+    // Whether model images with more than 8 bits per channel can be uploaded as Rgba16Float,
+    // which needs rendering (for the mip chain) and filtering
+    fn half_float_textures(&self) -> bool {
+        let features = self
+            .saved_gpu
+            .format_features(wgpu::TextureFormat::Rgba16Float);
+        features
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            && features
+                .flags
+                .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE)
+    }
+
+    // Uploads the model and keeps its GPU resources for draw_frame
+    pub async fn load_model(
+        &self,
+        bytes: &[u8],
+        scene: Option<usize>,
+    ) -> Result<(), RendererError> {
+        let device = self.saved_gpu.get_device();
+        let queue = self.saved_gpu.get_queue();
+        let (vertShader, fragShader, pbrFragShader) = &self.shaders;
+        let (model, material_pbr, texture) = self
+            .model_manager
+            .build_model(
+                bytes,
+                scene,
+                device.clone(),
+                queue.clone(),
+                self.half_float_textures(),
+            )
+            .await?;
+        let (camera_bgl, material_bgl, light_bgl) =
+            self.model_manager.bindGroupLayoutPBR_all(&device).await;
+        let (camera_bg, material_bg, light_bg, camera_buffer, joints_buffer, light_buffer) = self
+            .model_manager
+            .bindGroupPBR_all(
+                &device,
+                &queue,
+                &model,
+                material_pbr,
+                &texture,
+                camera_bgl.clone(),
+                material_bgl.clone(),
+                light_bgl.clone(),
+                &self.shadows.borrow(),
+            )
+            .await;
+        let mut pipelines = Vec::new();
+        for alpha_mode in [ALPHA_OPAQUE, ALPHA_MASK, ALPHA_BLEND] {
+            for double_sided in [false, true] {
+                debug_assert_eq!(pipelines.len(), pipeline_index(alpha_mode, double_sided));
+                pipelines.push(
+                    self.model_manager
+                        .buildPipelinePBR(
+                            &device,
+                            self.tone_mapper.borrow().hdr_format,
+                            &camera_bgl,
+                            &material_bgl,
+                            &light_bgl,
+                            &self.ibl.environment_layout,
+                            vertShader,
+                            pbrFragShader,
+                            self.tone_mapper.borrow().sample_count,
+                            alpha_mode,
+                            double_sided,
+                        )
+                        .await,
+                );
+            }
+        }
+        let shadow_pipeline =
+            self.shadows
+                .borrow()
+                .build_pipeline(&device, &camera_bgl, &material_bgl);
+        let (width, height) = self.saved_gpu.get_surface_size();
+        let depth_texture = self.model_manager.create_depth_texture(
+            &device,
+            width,
+            height,
+            self.tone_mapper.borrow().sample_count,
+        );
+        *self.resources.borrow_mut() = Some(ModelResources {
+            animation: AnimationPlayer::new(&model.scene),
+            model,
+            pipelines,
+            camera_bind_group: camera_bg,
+            material_bind_groups: material_bg,
+            light_bind_group: light_bg,
+            light_buffer,
+            camera_buffer,
+            joints_buffer,
+            shadow_pipeline,
+            depth_texture,
+        });
+        logger::log("model loaded successfully");
+        Ok(())
+    }
+
+    // Names (None when unnamed) and lengths in seconds of the model's clips, in glTF order
+    pub fn animations(&self) -> Vec<(Option<String>, f32)> {
+        self.resources
+            .borrow()
+            .as_ref()
+            .map_or(Vec::new(), |resources| {
+                resources
+                    .model
+                    .animations
+                    .iter()
+                    .map(|clip| (clip.name.clone(), clip.duration))
+                    .collect()
+            })
+    }
+
+    // Index of the first clip called `name`
+    pub fn animation_index(&self, name: &str) -> Option<usize> {
+        self.resources.borrow().as_ref().and_then(|resources| {
+            resources
+                .model
+                .animations
+                .iter()
+                .position(|clip| clip.name.as_deref() == Some(name))
+        })
+    }
+
+    // Plays clip `index` from its start, cross-fading from the playing clip over `fade` seconds
+    pub fn play_animation(&self, index: usize, fade: f32) -> Result<(), RendererError> {
+        let count = self.animations().len();
+        if index >= count {
+            return Err(RendererError::InvalidArgument(format!(
+                "no animation {}, the model has {}",
+                index, count
+            )));
+        }
+        self.animate(|player, clips| player.play(clips, index, fade.max(0.0)));
+        Ok(())
+    }
+
+    // Holds the current pose until resume_animation
+    pub fn pause_animation(&self) {
+        self.animate(|player, _| player.playing = false);
+    }
+
+    pub fn resume_animation(&self) {
+        self.animate(|player, _| player.playing = player.current().is_some());
+    }
+
+    pub fn is_animation_playing(&self) -> bool {
+        self.resources
+            .borrow()
+            .as_ref()
+            .is_some_and(|resources| resources.animation.playing)
+    }
+
+    // Loop (default) or stop at the end of the clip
+    pub fn set_animation_loop(&self, looping: bool) {
+        self.animate(|player, _| player.looping = looping);
+    }
+
+    // Playback rate, 1 = as authored, negative plays backwards
+    pub fn set_animation_speed(&self, speed: f32) {
+        self.animate(|player, _| player.speed = speed);
+    }
+
+    // Jumps to `time` seconds into the current clip
+    pub fn seek_animation(&self, time: f32) {
+        self.animate(|player, clips| player.seek(clips, time));
+    }
+
+    // Seconds into the current clip
+    pub fn animation_time(&self) -> f32 {
+        self.resources
+            .borrow()
+            .as_ref()
+            .map_or(0.0, |resources| resources.animation.time())
+    }
+
+    // Moves playback on by `delta` seconds; the frame loops call this before drawing
+    pub fn advance_animation(&self, delta: f32) {
+        if self.is_animation_playing() {
+            self.animate(|player, clips| player.advance(clips, delta));
+        }
+    }
+
+    // Names (None when unnamed) and morph target counts of the model's meshes, in glTF order
+    pub fn meshes(&self) -> Vec<(Option<String>, usize)> {
+        self.resources
+            .borrow()
+            .as_ref()
+            .map_or(Vec::new(), |resources| {
+                resources
+                    .model
+                    .meshes
+                    .iter()
+                    .map(|mesh| (mesh.name.clone(), mesh.morph_targets))
+                    .collect()
+            })
+    }
+
+    // Index of the first mesh called `name`
+    pub fn mesh_index(&self, name: &str) -> Option<usize> {
+        self.resources.borrow().as_ref().and_then(|resources| {
+            resources
+                .model
+                .meshes
+                .iter()
+                .position(|mesh| mesh.name.as_deref() == Some(name))
+        })
+    }
+
+    // Morph target weights of every instance of mesh `index`; clips that animate the weights
+    // override them while they play
+    pub fn set_morph_weights(&self, index: usize, weights: &[f32]) -> Result<(), RendererError> {
+        let count = self.meshes().len();
+        if index >= count {
+            return Err(RendererError::InvalidArgument(format!(
+                "no mesh {}, the model has {}",
+                index, count
+            )));
+        }
+        let mut resources = self.resources.borrow_mut();
+        let Some(resources) = resources.as_mut() else {
+            return Ok(());
+        };
+        let (model, player) = (&mut resources.model, &mut resources.animation);
+        for (node, scene_node) in model.scene.nodes.iter_mut().enumerate() {
+            if scene_node.mesh == Some(index) {
+                scene_node.weights = weights.to_vec();
+                player.set_rest_weights(node, weights);
+            }
+        }
+        player.apply(&model.animations, &mut model.scene);
+        Ok(())
+    }
+
+    // Changes the player and poses the scene for the next frame
+    fn animate(&self, change: impl FnOnce(&mut AnimationPlayer, &[Clip])) {
+        let mut resources = self.resources.borrow_mut();
+        let Some(resources) = resources.as_mut() else {
+            return;
+        };
+        change(&mut resources.animation, &resources.model.animations);
+        resources
+            .animation
+            .apply(&resources.model.animations, &mut resources.model.scene);
+    }
+
+    // Draws a single frame with the current camera; no-op until a model is loaded
+    pub fn draw_frame(&self) {
+        let resources = self.resources.borrow();
+        let (Some(resources), Some(surface)) = (resources.as_ref(), self.saved_gpu.get_surface())
+        else {
+            return;
+        };
+        self.upload_uniforms(resources);
+        let draw_order = resources
+            .model
+            .draw_order(self.camera.borrow().view_matrix());
+        self.render_manager.draw_frame(
+            &self.saved_gpu.get_device(),
+            &self.saved_gpu.get_queue(),
+            surface,
+            resources,
+            &draw_order,
+            &self.environment.borrow().bind_group,
+            &self.background.borrow(),
+            &self.shadows.borrow(),
+            &self.tone_mapper.borrow(),
+        );
+    }
+
+    // Same as draw_frame but into `view`, which must have the surface format and size
+    pub fn draw_to_view(&self, view: &wgpu::TextureView) -> bool {
+        let resources = self.resources.borrow();
+        let Some(resources) = resources.as_ref() else {
+            return false;
+        };
+        self.upload_uniforms(resources);
+        let draw_order = resources
+            .model
+            .draw_order(self.camera.borrow().view_matrix());
+        self.render_manager.draw_to_view(
+            &self.saved_gpu.get_device(),
+            &self.saved_gpu.get_queue(),
+            view,
+            resources,
+            &draw_order,
+            &self.environment.borrow().bind_group,
+            &self.background.borrow(),
+            &self.shadows.borrow(),
+            &self.tone_mapper.borrow(),
+        );
+        true
+    }
+
+    // Camera, joint, light, shadow and tone mapping uniforms for the next frame
+    fn upload_uniforms(&self, resources: &ModelResources) {
+        let queue = self.saved_gpu.get_queue();
+        let camera = self.camera.borrow();
+        self.background.borrow().update(&queue, &camera);
+        self.tone_mapper.borrow().update(&queue);
+        let camera_uniforms = self.model_manager.camera_uniform_bytes(
+            &self.saved_gpu.get_device(),
+            &resources.model,
+            camera.view_proj_matrix(),
+            camera.position,
+        );
+        self.render_manager
+            .update_camera(&queue, &resources.camera_buffer, &camera_uniforms);
+        let model = &resources.model;
+        if !model.skins.is_empty() {
+            queue.write_buffer(
+                &resources.joints_buffer,
+                0,
+                &joint_palette_bytes(&model.skins, &model.scene),
+            );
+        }
+        let scene_lights = model
+            .lights
+            .iter()
+            .map(|(node, light)| light.transformed(model.scene.world[*node]));
+        let lights: Vec<Light> = scene_lights
+            .chain(self.lights.borrow().iter().copied())
+            .take(MAX_LIGHTS)
+            .collect();
+        self.render_manager.update_lights(
+            &queue,
+            &resources.light_buffer,
+            &lights_uniform(lights.iter().copied()),
+        );
+        self.shadows.borrow_mut().update(
+            &queue,
+            model,
+            &lights,
+            self.environment.borrow().key_direction,
+            &camera,
+        );
+    }
+
+    // Reconfigures the surface and depth buffer, sizes in physical pixels
+    pub fn resize(&self, width: u32, height: u32) {
+        if !self.saved_gpu.resize_surface(width, height) {
+            return;
+        }
+        let (width, height) = self.saved_gpu.get_surface_size();
+        self.camera.borrow_mut().aspect = width as f32 / height as f32;
+        self.tone_mapper
+            .borrow_mut()
+            .resize(&self.saved_gpu.get_device(), width, height);
+        if let Some(resources) = self.resources.borrow_mut().as_mut() {
+            resources.depth_texture = self.model_manager.create_depth_texture(
+                &self.saved_gpu.get_device(),
+                width,
+                height,
+                self.tone_mapper.borrow().sample_count,
+            );
+        }
+    }
+}
@@ -0,0 +1,453 @@
+// Golden-image regression tests for the PBR pipeline.
+//
+// Every case is rendered headlessly on a software adapter and compared against
+// tests/golden/<name>.png with a perceptual (YIQ) colour distance, like pixelmatch.
+// On failure the render and a diff image (mismatches in red) are written to
+// target/tmp/golden/. Run with UPDATE_GOLDEN=1 to record new cases or re-record the
+// references after an intended shading change, and review the new PNGs before committing
+// them. A missing reference or software adapter fails the test.
+#![cfg(not(target_arch = "wasm32"))]
+
+use renderer::background::BackgroundMode;
+use renderer::offscreen::{HeadlessRenderer, RenderedImage};
+use renderer::renderer_error::RendererError;
+use renderer::renderer_options::RendererOptions;
+use renderer::shadows::ShadowSettings;
+use renderer::tone_mapping::ToneMapping;
+use std::path::{Path, PathBuf};
+
+const SIZE: u32 = 256;
+// per-pixel YIQ distance a pixel may have before it counts as different (0..1)
+const PIXEL_THRESHOLD: f32 = 0.1;
+// share of pixels allowed to differ, absorbs rasterizer and driver noise
+const MAX_MISMATCH_RATIO: f32 = 0.005;
+
+struct GoldenCase {
+    name: &'static str,
+    model: &'static str,
+    // orbit angles in radians and dolly, applied to the default camera
+    yaw: f32,
+    pitch: f32,
+    zoom: f32,
+    // None keeps the default dark grey clear color
+    background: Option<BackgroundMode>,
+    // None keeps the default shadows (on, without ground)
+    shadows: Option<ShadowSettings>,
+    // (tone mapper, exposure), None keeps PBR Neutral at exposure 1
+    tone_mapping: Option<(ToneMapping, f32)>,
+    // (clip, cross-fade seconds, seconds advanced afterwards), played in order
+    animation: &'static [(usize, f32, f32)],
+}
+
+const CASES: &[GoldenCase] = &[
+    GoldenCase {
+        name: "damaged_helmet",
+        model: "../n3d_pure/models/DamagedHelmet.glb",
+        yaw: 0.0,
+        pitch: 0.0,
+        zoom: -0.3,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "damaged_helmet_side",
+        model: "../n3d_pure/models/DamagedHelmet.glb",
+        yaw: 1.2,
+        pitch: 0.3,
+        zoom: -0.3,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "emissive_cube",
+        model: "tests/assets/emissive_cube.gltf",
+        yaw: 0.0,
+        pitch: 0.0,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        // non-uniformly scaled parent, shaded with inverse transpose normals
+        name: "sheared_cube",
+        model: "tests/assets/sheared_cube.gltf",
+        yaw: 0.0,
+        pitch: 0.0,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        // metallic and roughness left at their defaults of 1.0, still a PBR material
+        name: "default_factors_cube",
+        model: "tests/assets/default_factors_cube.gltf",
+        yaw: 0.0,
+        pitch: 0.0,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "checker_quad",
+        model: "tests/assets/checker_quad.gltf",
+        yaw: 0.4,
+        pitch: 0.0,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "grey16_quad",
+        model: "tests/assets/grey16_quad.gltf",
+        yaw: 0.4,
+        pitch: 0.0,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "alpha_modes",
+        model: "tests/assets/alpha_modes.gltf",
+        yaw: 0.3,
+        pitch: 0.0,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "alpha_modes_ground",
+        model: "tests/assets/alpha_modes.gltf",
+        yaw: 0.5,
+        pitch: 0.9,
+        zoom: 0.2,
+        background: Some(BackgroundMode::Color([0.8, 0.8, 0.8])),
+        shadows: Some(ShadowSettings {
+            enabled: true,
+            bias: 0.002,
+            normal_bias: 1.5,
+            softness: 2,
+            strength: 0.6,
+            ground: true,
+            ground_opacity: 0.7,
+        }),
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "skinned_bar",
+        model: "tests/assets/skinned_bar.gltf",
+        yaw: 0.3,
+        pitch: 0.2,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "skinned_bar_ground",
+        model: "tests/assets/skinned_bar.gltf",
+        yaw: 0.3,
+        pitch: 0.5,
+        zoom: 0.5,
+        background: Some(BackgroundMode::Color([0.8, 0.8, 0.8])),
+        shadows: Some(ShadowSettings {
+            enabled: true,
+            bias: 0.002,
+            normal_bias: 1.5,
+            softness: 2,
+            strength: 0.6,
+            ground: true,
+            ground_opacity: 0.7,
+        }),
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "animated_boxes",
+        model: "tests/assets/animated_boxes.gltf",
+        yaw: 0.0,
+        pitch: 0.2,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[(0, 0.0, 1.25)],
+    },
+    GoldenCase {
+        name: "animated_boxes_fade",
+        model: "tests/assets/animated_boxes.gltf",
+        yaw: 0.0,
+        pitch: 0.2,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[(0, 0.0, 0.5), (1, 1.0, 0.5)],
+    },
+    GoldenCase {
+        name: "morph_grid",
+        model: "tests/assets/morph_grid.gltf",
+        yaw: 0.6,
+        pitch: 0.4,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "morph_grid_animated",
+        model: "tests/assets/morph_grid.gltf",
+        yaw: 0.6,
+        pitch: 0.4,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[(0, 0.0, 0.5)],
+    },
+    GoldenCase {
+        name: "lit_cube",
+        model: "tests/assets/lit_cube.gltf",
+        yaw: 0.3,
+        pitch: 0.2,
+        zoom: 0.0,
+        background: None,
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "damaged_helmet_skybox",
+        model: "../n3d_pure/models/DamagedHelmet.glb",
+        yaw: 0.6,
+        pitch: -0.2,
+        zoom: -0.3,
+        background: Some(BackgroundMode::Skybox { blur: 0.25 }),
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "damaged_helmet_skybox_sharp",
+        model: "../n3d_pure/models/DamagedHelmet.glb",
+        yaw: 0.6,
+        pitch: -0.2,
+        zoom: -0.3,
+        background: Some(BackgroundMode::Skybox { blur: 0.0 }),
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "checker_quad_transparent",
+        model: "tests/assets/checker_quad.gltf",
+        yaw: 0.4,
+        pitch: 0.0,
+        zoom: 0.0,
+        background: Some(BackgroundMode::Transparent),
+        shadows: None,
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "damaged_helmet_ground",
+        model: "../n3d_pure/models/DamagedHelmet.glb",
+        yaw: 0.4,
+        pitch: 0.5,
+        zoom: 0.3,
+        background: Some(BackgroundMode::Color([0.8, 0.8, 0.8])),
+        shadows: Some(ShadowSettings {
+            enabled: true,
+            bias: 0.002,
+            normal_bias: 1.5,
+            softness: 2,
+            strength: 0.6,
+            ground: true,
+            ground_opacity: 0.7,
+        }),
+        tone_mapping: None,
+        animation: &[],
+    },
+    GoldenCase {
+        name: "damaged_helmet_agx",
+        model: "../n3d_pure/models/DamagedHelmet.glb",
+        yaw: 0.0,
+        pitch: 0.0,
+        zoom: -0.3,
+        background: None,
+        shadows: None,
+        tone_mapping: Some((ToneMapping::AgX, 2.0)),
+        animation: &[],
+    },
+];
+
+#[test]
+fn pbr_golden_images() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+
+    let mut failures = Vec::new();
+    for case in CASES {
+        let image = match render_case(root, case) {
+            Ok(image) => image,
+            Err(RendererError::NoAdapter(reason)) => panic!(
+                "no software adapter, install mesa (llvmpipe / lavapipe) to run the golden images: {}",
+                reason
+            ),
+            Err(e) => panic!("{}: {}", case.name, e),
+        };
+        let golden_path = root.join("tests/golden").join(format!("{}.png", case.name));
+        if update {
+            std::fs::write(&golden_path, image.to_png().unwrap()).unwrap();
+            eprintln!("recorded {}", golden_path.display());
+            continue;
+        }
+        if !golden_path.exists() {
+            let actual_path = out_dir.join(format!("{}.actual.png", case.name));
+            std::fs::write(&actual_path, image.to_png().unwrap()).unwrap();
+            failures.push(format!(
+                "{}: no golden image, review {} and record it with UPDATE_GOLDEN=1",
+                case.name,
+                actual_path.display()
+            ));
+            continue;
+        }
+        let golden = RenderedImage::from_png(&std::fs::read(&golden_path).unwrap()).unwrap();
+        if let Some(report) = compare(case.name, &golden, &image, &out_dir) {
+            failures.push(report);
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+fn render_case(root: &Path, case: &GoldenCase) -> Result<RenderedImage, RendererError> {
+    let bytes = std::fs::read(root.join(case.model))
+        .unwrap_or_else(|e| panic!("{}: could not read {}: {}", case.name, case.model, e));
+    let options = RendererOptions {
+        force_fallback_adapter: true,
+        ..RendererOptions::default()
+    };
+    pollster::block_on(async {
+        let renderer = HeadlessRenderer::new(SIZE, SIZE, &options).await?;
+        renderer.load_model(&bytes, None).await?;
+        if let Some(background) = &case.background {
+            renderer.set_background(background.clone())?;
+        }
+        if let Some(shadows) = case.shadows {
+            renderer.set_shadows(shadows);
+        }
+        if let Some((mapping, exposure)) = case.tone_mapping {
+            renderer.set_tone_mapping(mapping);
+            renderer.set_exposure(exposure);
+        }
+        for &(clip, fade, seconds) in case.animation {
+            renderer.play_animation(clip, fade)?;
+            renderer.advance_animation(seconds);
+        }
+        renderer.rotate(case.yaw, case.pitch);
+        renderer.zoom(case.zoom);
+        renderer.render()
+    })
+}
+
+// Returns a failure message and writes the actual and diff images, or None on a match
+fn compare(
+    name: &str,
+    golden: &RenderedImage,
+    actual: &RenderedImage,
+    out_dir: &Path,
+) -> Option<String> {
+    let actual_path = out_dir.join(format!("{}.actual.png", name));
+    if (golden.width, golden.height) != (actual.width, actual.height) {
+        std::fs::write(&actual_path, actual.to_png().unwrap()).unwrap();
+        return Some(format!(
+            "{}: size {}x{} differs from golden {}x{} (actual: {})",
+            name,
+            actual.width,
+            actual.height,
+            golden.width,
+            golden.height,
+            actual_path.display()
+        ));
+    }
+
+    let mut diff = Vec::with_capacity(actual.rgba.len());
+    let mut mismatched = 0usize;
+    for (g, a) in golden.rgba.chunks_exact(4).zip(actual.rgba.chunks_exact(4)) {
+        if color_delta(g, a) > PIXEL_THRESHOLD {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            // faded grayscale copy of the golden pixel for context
+            let gray = 192 + (luma(g) * 63.0) as u8;
+            diff.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+    let pixels = (actual.width * actual.height) as f32;
+    let ratio = mismatched as f32 / pixels;
+    if ratio <= MAX_MISMATCH_RATIO {
+        return None;
+    }
+
+    let diff_path: PathBuf = out_dir.join(format!("{}.diff.png", name));
+    let diff_image = RenderedImage {
+        width: actual.width,
+        height: actual.height,
+        rgba: diff,
+    };
+    std::fs::write(&actual_path, actual.to_png().unwrap()).unwrap();
+    std::fs::write(&diff_path, diff_image.to_png().unwrap()).unwrap();
+    Some(format!(
+        "{}: {} pixels ({:.2}%) differ (allowed {:.2}%), actual: {}, diff: {}",
+        name,
+        mismatched,
+        ratio * 100.0,
+        MAX_MISMATCH_RATIO * 100.0,
+        actual_path.display(),
+        diff_path.display()
+    ))
+}
+
+// Normalised YIQ distance between two sRGB pixels blended over white, 0 = identical
+fn color_delta(a: &[u8], b: &[u8]) -> f32 {
+    let (ya, ia, qa) = yiq(a);
+    let (yb, ib, qb) = yiq(b);
+    let (dy, di, dq) = (ya - yb, ia - ib, qa - qb);
+    // 35215 is the largest possible weighted distance (black vs white)
+    ((0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq) / 35215.0).sqrt()
+}
+
+fn yiq(pixel: &[u8]) -> (f32, f32, f32) {
+    let alpha = pixel[3] as f32 / 255.0;
+    let blend = |c: u8| 255.0 + (c as f32 - 255.0) * alpha;
+    let (r, g, b) = (blend(pixel[0]), blend(pixel[1]), blend(pixel[2]));
+    (
+        r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_2,
+        r * 0.595_978 - g * 0.2741761 - b * 0.321_801_9,
+        r * 0.211_470_2 - g * 0.522_617_1 + b * 0.311_146_9,
+    )
+}
+
+fn luma(pixel: &[u8]) -> f32 {
+    yiq(pixel).0 / 255.0
+}